@@ -1,14 +1,16 @@
-use std::sync::*;
-
 use crate::blocks::constant::ConstantBlock;
 use crate::blocks::{BlockType, SignalBlock, SignalBlockChildren, SignalSource};
+use crate::graph::Context;
 
+#[derive(Default)]
 pub struct AmplifierBlock {
     sources: Vec<(SignalSource, SignalSource)>,
 }
 
 
 impl AmplifierBlock {
+    pub const PARAMETERS: &[&str] = &["source#", "src#", "amplify#", "amp#"];
+
     pub fn update_source(&mut self, n: usize, first: bool, source: SignalSource) {
         while self.sources.len() <= n {
             self.sources.push((SignalSource::default(), SignalSource::new_anonymous(ConstantBlock::new(1.0))));
@@ -23,10 +25,10 @@ impl AmplifierBlock {
 }
 
 impl SignalBlock for AmplifierBlock {
-    fn step(&mut self) {
+    fn step(&mut self, ctx: &Context) {
         for (s1, s2) in &mut self.sources {
-            s1.step();
-            s2.step();
+            s1.step(ctx);
+            s2.step(ctx);
         }
     }
 
@@ -56,20 +58,12 @@ impl SignalBlock for AmplifierBlock {
         self.sync_children_from(other);
     }
 
-    fn children(&self) -> SignalBlockChildren {
+    fn children(&self) -> SignalBlockChildren<'_> {
         let mut children = SignalBlockChildren::new();
-        for (s1, s2) in &self.sources {
-            children.push(s1.inner());
-            children.push(s2.inner());
+        for (n, (s1, s2)) in self.sources.iter().enumerate() {
+            children.push(format!("src{n}"), s1);
+            children.push(format!("amp{n}"), s2);
         }
         children
     }
 }
-
-impl Default for AmplifierBlock {
-    fn default() -> Self {
-        AmplifierBlock {
-            sources: Vec::new()
-        }
-    }
-}
@@ -1,4 +1,5 @@
 use crate::blocks::{BlockType, SignalBlock};
+use crate::graph::{BlockBuffer, Context, Frame};
 
 pub struct ConstantBlock {
     val: f32,
@@ -12,7 +13,11 @@ impl ConstantBlock {
 
 
 impl SignalBlock for ConstantBlock {
-    fn step(&mut self) {}
+    fn step(&mut self, _ctx: &Context) {}
+
+    fn process(&mut self, _ctx: &Context, buffer: &mut BlockBuffer) {
+        buffer.frames_mut().fill(Frame { mono: self.val, left: self.val, right: self.val });
+    }
 
     fn get_mono(&self) -> f32 {
         self.val
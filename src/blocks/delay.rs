@@ -0,0 +1,203 @@
+use std::any::Any;
+
+use crate::blocks::constant::ConstantBlock;
+use crate::blocks::{BlockType, SignalBlock, SignalBlockChildren, SignalSource};
+use crate::graph::Context;
+use crate::transport::Transport;
+
+const MAX_DELAY_SECONDS: f32 = 10.0;
+const TIME_SMOOTHING: f32 = 0.0005;
+
+pub struct DelayBlock {
+    src: SignalSource,
+    time: SignalSource,
+    bpm: Option<SignalSource>,
+    feedback: SignalSource,
+    mix: SignalSource,
+    unit: TimeUnit,
+    left: DelayLine,
+    right: DelayLine,
+    delay: f32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum TimeUnit {
+    Seconds,
+    Beats,
+}
+
+struct DelayLine {
+    buffer: Vec<f32>,
+    pos: usize,
+    out: f32,
+}
+
+
+impl DelayBlock {
+    pub const PARAMETERS: &[&str] = &["source", "src", "time", "t", "beats", "bpm", "feedback", "fb", "mix"];
+
+    pub fn update_source(&mut self, src: SignalSource) {
+        self.src = src;
+    }
+
+    pub fn update_time(&mut self, time: SignalSource, unit: TimeUnit) {
+        self.time = time;
+        self.unit = unit;
+    }
+
+    pub fn update_bpm(&mut self, bpm: SignalSource) {
+        self.bpm = Some(bpm);
+    }
+
+    pub fn update_feedback(&mut self, feedback: SignalSource) {
+        self.feedback = feedback;
+    }
+
+    pub fn update_mix(&mut self, mix: SignalSource) {
+        self.mix = mix;
+    }
+
+    fn target_delay(&self, transport: &Transport) -> f32 {
+        let sample_rate = transport.sample_rate() as f32;
+        let seconds = match self.unit {
+            TimeUnit::Seconds => self.time.get_mono(),
+            TimeUnit::Beats => {
+                let bpm = self.bpm.as_ref().map_or(transport.bpm(), |bpm| bpm.get_mono());
+                self.time.get_mono() * 60.0 / bpm.max(1.0)
+            },
+        };
+        let max = (self.left.buffer.len() - 2) as f32;
+        (seconds * sample_rate).clamp(1.0, max)
+    }
+}
+
+impl DelayLine {
+    fn new(sample_rate: u32) -> Self {
+        let len = (MAX_DELAY_SECONDS * sample_rate as f32) as usize;
+        DelayLine {
+            buffer: vec![0.0; len],
+            pos: 0,
+            out: 0.0,
+        }
+    }
+
+    fn read(&self, delay: f32) -> f32 {
+        let len = self.buffer.len();
+        let offset = delay.floor();
+        let fract = delay - offset;
+
+        let i0 = (self.pos + len - offset as usize) % len;
+        let i1 = (i0 + len - 1) % len;
+        self.buffer[i0] * (1.0 - fract) + self.buffer[i1] * fract
+    }
+
+    fn process(&mut self, input: f32, delay: f32, feedback: f32, mix: f32) {
+        let wet = self.read(delay);
+        self.buffer[self.pos] = input + wet * feedback;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        self.out = input * (1.0 - mix) + wet * mix;
+    }
+}
+
+impl Clone for DelayLine {
+    fn clone(&self) -> Self {
+        DelayLine { buffer: self.buffer.clone(), pos: self.pos, out: self.out }
+    }
+
+    // runs on the audio thread during a reload, so reuse the buffer instead of reallocating it
+    fn clone_from(&mut self, other: &Self) {
+        if self.buffer.len() == other.buffer.len() {
+            self.buffer.copy_from_slice(&other.buffer);
+        } else {
+            self.buffer.clone_from(&other.buffer);
+        }
+        self.pos = other.pos;
+        self.out = other.out;
+    }
+}
+
+
+impl SignalBlock for DelayBlock {
+    fn step(&mut self, ctx: &Context) {
+        self.src.step(ctx);
+        self.time.step(ctx);
+        if let Some(bpm) = &mut self.bpm {
+            bpm.step(ctx);
+        }
+        self.feedback.step(ctx);
+        self.mix.step(ctx);
+
+        // glide towards the target delay to avoid zipper noise when it changes
+        let target = self.target_delay(&ctx.transport);
+        if self.delay < 1.0 {
+            self.delay = target;
+        } else {
+            self.delay += (target - self.delay) * TIME_SMOOTHING;
+        }
+
+        let feedback = self.feedback.get_mono().clamp(-0.99, 0.99);
+        let mix = self.mix.get_mono().clamp(0.0, 1.0);
+        self.left.process(self.src.get_left(), self.delay, feedback, mix);
+        self.right.process(self.src.get_right(), self.delay, feedback, mix);
+    }
+
+    fn get_mono(&self) -> f32 {
+        (self.left.out + self.right.out) / 2.0
+    }
+
+    fn get_left(&self) -> f32 {
+        self.left.out
+    }
+
+    fn get_right(&self) -> f32 {
+        self.right.out
+    }
+
+    fn block_type(&self) -> super::BlockType {
+        BlockType::Delay
+    }
+
+    fn prepare(&mut self, sample_rate: u32) {
+        self.left = DelayLine::new(sample_rate);
+        self.right = DelayLine::new(sample_rate);
+        self.prepare_children(sample_rate);
+    }
+
+    fn sync_from(&mut self, other: &dyn SignalBlock) {
+        if let Some(other) = (other as &dyn Any).downcast_ref::<Self>() {
+            self.left.clone_from(&other.left);
+            self.right.clone_from(&other.right);
+            self.delay = other.delay;
+        }
+
+        self.sync_children_from(other);
+    }
+
+    fn children(&self) -> SignalBlockChildren<'_> {
+        let mut children = SignalBlockChildren::new();
+        children.push("src", &self.src);
+        children.push("time", &self.time);
+        children.push("feedback", &self.feedback);
+        children.push("mix", &self.mix);
+        if let Some(bpm) = &self.bpm {
+            children.push("bpm", bpm);
+        }
+        children
+    }
+}
+
+impl Default for DelayBlock {
+    fn default() -> Self {
+        DelayBlock {
+            src: SignalSource::default(),
+            time: SignalSource::new_anonymous(ConstantBlock::new(0.25)),
+            bpm: None,
+            feedback: SignalSource::new_anonymous(ConstantBlock::new(0.4)),
+            mix: SignalSource::new_anonymous(ConstantBlock::new(0.5)),
+            unit: TimeUnit::Seconds,
+            left: DelayLine::new(0),
+            right: DelayLine::new(0),
+            delay: 0.0,
+        }
+    }
+}
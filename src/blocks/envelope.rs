@@ -0,0 +1,155 @@
+use std::any::Any;
+
+use crate::blocks::constant::ConstantBlock;
+use crate::blocks::{BlockType, SignalBlock, SignalBlockChildren, SignalSource};
+use crate::graph::Context;
+
+pub struct EnvelopeBlock {
+    attack: SignalSource,
+    decay: SignalSource,
+    sustain: SignalSource,
+    release: SignalSource,
+    gate: SignalSource,
+    stage: Stage,
+    level: f32,
+    release_level: f32,
+    gate_open: bool,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+
+impl EnvelopeBlock {
+    pub const PARAMETERS: &[&str] = &["attack", "a", "decay", "d", "sustain", "s", "release", "r", "gate", "g"];
+
+    pub fn update_attack(&mut self, attack: SignalSource) {
+        self.attack = attack;
+    }
+
+    pub fn update_decay(&mut self, decay: SignalSource) {
+        self.decay = decay;
+    }
+
+    pub fn update_sustain(&mut self, sustain: SignalSource) {
+        self.sustain = sustain;
+    }
+
+    pub fn update_release(&mut self, release: SignalSource) {
+        self.release = release;
+    }
+
+    pub fn update_gate(&mut self, gate: SignalSource) {
+        self.gate = gate;
+    }
+
+    fn increment(time: f32, distance: f32, sample_rate: f32) -> f32 {
+        let samples = time * sample_rate;
+        if samples <= 1.0 {
+            distance
+        } else {
+            distance / samples
+        }
+    }
+}
+
+
+impl SignalBlock for EnvelopeBlock {
+    fn step(&mut self, ctx: &Context) {
+        self.attack.step(ctx);
+        self.decay.step(ctx);
+        self.sustain.step(ctx);
+        self.release.step(ctx);
+        self.gate.step(ctx);
+
+        let gate_open = self.gate.get_mono() > 0.0;
+        if gate_open && !self.gate_open {
+            self.stage = Stage::Attack;
+        } else if !gate_open && self.gate_open {
+            self.stage = Stage::Release;
+            self.release_level = self.level;
+        }
+        self.gate_open = gate_open;
+
+        let sustain = self.sustain.get_mono().clamp(0.0, 1.0);
+        let sample_rate = ctx.sample_rate();
+
+        use Stage::*;
+        match self.stage {
+            Idle => self.level = 0.0,
+            Attack => {
+                self.level += Self::increment(self.attack.get_mono(), 1.0, sample_rate);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Decay;
+                }
+            },
+            Decay => {
+                self.level -= Self::increment(self.decay.get_mono(), 1.0 - sustain, sample_rate);
+                if self.level <= sustain {
+                    self.level = sustain;
+                    self.stage = Sustain;
+                }
+            },
+            Sustain => self.level = sustain,
+            Release => {
+                self.level -= Self::increment(self.release.get_mono(), self.release_level, sample_rate);
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = Idle;
+                }
+            },
+        }
+    }
+
+    fn get_mono(&self) -> f32 {
+        self.level
+    }
+
+    fn block_type(&self) -> super::BlockType {
+        BlockType::Envelope
+    }
+
+    fn sync_from(&mut self, other: &dyn SignalBlock) {
+        if let Some(other) = (other as &dyn Any).downcast_ref::<Self>() {
+            self.stage = other.stage;
+            self.level = other.level;
+            self.release_level = other.release_level;
+            self.gate_open = other.gate_open;
+        }
+
+        self.sync_children_from(other);
+    }
+
+    fn children(&self) -> SignalBlockChildren<'_> {
+        let mut children = SignalBlockChildren::new();
+        children.push("attack", &self.attack);
+        children.push("decay", &self.decay);
+        children.push("sustain", &self.sustain);
+        children.push("release", &self.release);
+        children.push("gate", &self.gate);
+        children
+    }
+}
+
+impl Default for EnvelopeBlock {
+    fn default() -> Self {
+        EnvelopeBlock {
+            attack: SignalSource::new_anonymous(ConstantBlock::new(0.01)),
+            decay: SignalSource::new_anonymous(ConstantBlock::new(0.1)),
+            sustain: SignalSource::new_anonymous(ConstantBlock::new(0.7)),
+            release: SignalSource::new_anonymous(ConstantBlock::new(0.2)),
+            gate: SignalSource::default(),
+            stage: Stage::Idle,
+            level: 0.0,
+            release_level: 0.0,
+            gate_open: false,
+        }
+    }
+}
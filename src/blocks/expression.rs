@@ -0,0 +1,86 @@
+use crate::blocks::{BlockType, SignalBlock, SignalBlockChildren, SignalSource};
+use crate::graph::{BlockBuffer, Context, Frame};
+
+pub struct ExpressionBlock {
+    operator: Operator,
+    lhs: SignalSource,
+    rhs: SignalSource,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+
+impl ExpressionBlock {
+    pub fn new(operator: Operator, lhs: SignalSource, rhs: SignalSource) -> Self {
+        ExpressionBlock { operator, lhs, rhs }
+    }
+}
+
+impl Operator {
+    fn apply(self, lhs: f32, rhs: f32) -> f32 {
+        use Operator::*;
+        match self {
+            Add => lhs + rhs,
+            Subtract => lhs - rhs,
+            Multiply => lhs * rhs,
+            // avoid feeding infinities into the rest of the graph
+            Divide => if rhs == 0.0 { 0.0 } else { lhs / rhs },
+        }
+    }
+}
+
+
+impl SignalBlock for ExpressionBlock {
+    fn step(&mut self, ctx: &Context) {
+        self.lhs.step(ctx);
+        self.rhs.step(ctx);
+    }
+
+    fn process(&mut self, ctx: &Context, buffer: &mut BlockBuffer) {
+        self.lhs.process(ctx);
+        self.rhs.process(ctx);
+
+        let operator = self.operator;
+        let inputs = self.lhs.frames().iter().zip(self.rhs.frames());
+        for (frame, (lhs, rhs)) in buffer.frames_mut().iter_mut().zip(inputs) {
+            *frame = Frame {
+                mono: operator.apply(lhs.mono, rhs.mono),
+                left: operator.apply(lhs.left, rhs.left),
+                right: operator.apply(lhs.right, rhs.right),
+            };
+        }
+    }
+
+    fn get_mono(&self) -> f32 {
+        self.operator.apply(self.lhs.get_mono(), self.rhs.get_mono())
+    }
+
+    fn get_left(&self) -> f32 {
+        self.operator.apply(self.lhs.get_left(), self.rhs.get_left())
+    }
+
+    fn get_right(&self) -> f32 {
+        self.operator.apply(self.lhs.get_right(), self.rhs.get_right())
+    }
+
+    fn block_type(&self) -> super::BlockType {
+        BlockType::Expression
+    }
+
+    fn sync_from(&mut self, other: &dyn SignalBlock) {
+        self.sync_children_from(other);
+    }
+
+    fn children(&self) -> SignalBlockChildren<'_> {
+        let mut children = SignalBlockChildren::new();
+        children.push("lhs", &self.lhs);
+        children.push("rhs", &self.rhs);
+        children
+    }
+}
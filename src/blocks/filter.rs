@@ -0,0 +1,195 @@
+use std::any::Any;
+use std::f32::consts::*;
+
+use crate::blocks::constant::ConstantBlock;
+use crate::blocks::{BlockType, SignalBlock, SignalBlockChildren, SignalSource};
+use crate::graph::{BlockBuffer, Context, Frame};
+
+pub struct FilterBlock {
+    src: SignalSource,
+    cutoff: SignalSource,
+    resonance: SignalSource,
+    gain: SignalSource,
+    mode: FilterMode,
+    left: Biquad,
+    right: Biquad,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum FilterMode {
+    Lowpass,
+    Highpass,
+    Bandpass,
+    Notch,
+    Peak,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Biquad {
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+    out: f32,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+
+impl FilterBlock {
+    pub const PARAMETERS: &[&str] = &["source", "src", "mode", "cutoff", "freq", "resonance", "res", "q", "gain"];
+
+    pub fn update_source(&mut self, src: SignalSource) {
+        self.src = src;
+    }
+
+    pub fn update_cutoff(&mut self, cutoff: SignalSource) {
+        self.cutoff = cutoff;
+    }
+
+    pub fn update_resonance(&mut self, resonance: SignalSource) {
+        self.resonance = resonance;
+    }
+
+    pub fn update_gain(&mut self, gain: SignalSource) {
+        self.gain = gain;
+    }
+
+    pub fn update_mode(&mut self, mode: FilterMode) {
+        self.mode = mode;
+    }
+
+    // see Robert Bristow-Johnson's "Audio EQ Cookbook"
+    fn coefficients(&self, sample_rate: f32, cutoff: f32, resonance: f32, gain: f32) -> Coefficients {
+        let cutoff = cutoff.clamp(10.0, (sample_rate * 0.49).max(10.0));
+        let q = resonance.max(0.1);
+
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+
+        use FilterMode::*;
+        let (b0, b1, b2, a0, a1, a2) = match self.mode {
+            Lowpass => ((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            Highpass => ((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            Bandpass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            Peak => {
+                let a = 10.0_f32.powf(gain / 40.0);
+                (1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a)
+            },
+        };
+
+        Coefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+impl Biquad {
+    fn process(&mut self, c: &Coefficients, x: f32) {
+        let y = c.b0 * x + c.b1 * self.x1 + c.b2 * self.x2 - c.a1 * self.y1 - c.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        self.out = y;
+    }
+}
+
+
+impl SignalBlock for FilterBlock {
+    fn step(&mut self, ctx: &Context) {
+        self.src.step(ctx);
+        self.cutoff.step(ctx);
+        self.resonance.step(ctx);
+        self.gain.step(ctx);
+
+        let coefficients = self.coefficients(ctx.sample_rate(), self.cutoff.get_mono(), self.resonance.get_mono(), self.gain.get_mono());
+        self.left.process(&coefficients, self.src.get_left());
+        self.right.process(&coefficients, self.src.get_right());
+    }
+
+    fn process(&mut self, ctx: &Context, buffer: &mut BlockBuffer) {
+        self.src.process(ctx);
+        self.cutoff.process(ctx);
+        self.resonance.process(ctx);
+        self.gain.process(ctx);
+
+        for (i, frame) in buffer.frames_mut().iter_mut().enumerate() {
+            let coefficients = self.coefficients(
+                ctx.sample_rate(),
+                self.cutoff.frames()[i].mono,
+                self.resonance.frames()[i].mono,
+                self.gain.frames()[i].mono,
+            );
+            let input = self.src.frames()[i];
+            self.left.process(&coefficients, input.left);
+            self.right.process(&coefficients, input.right);
+            *frame = Frame {
+                mono: (self.left.out + self.right.out) / 2.0,
+                left: self.left.out,
+                right: self.right.out,
+            };
+        }
+    }
+
+    fn get_mono(&self) -> f32 {
+        (self.left.out + self.right.out) / 2.0
+    }
+
+    fn get_left(&self) -> f32 {
+        self.left.out
+    }
+
+    fn get_right(&self) -> f32 {
+        self.right.out
+    }
+
+    fn block_type(&self) -> super::BlockType {
+        BlockType::Filter
+    }
+
+    fn sync_from(&mut self, other: &dyn SignalBlock) {
+        if let Some(other) = (other as &dyn Any).downcast_ref::<Self>() {
+            self.left = other.left;
+            self.right = other.right;
+        }
+
+        self.sync_children_from(other);
+    }
+
+    fn children(&self) -> SignalBlockChildren<'_> {
+        let mut children = SignalBlockChildren::new();
+        children.push("src", &self.src);
+        children.push("cutoff", &self.cutoff);
+        children.push("resonance", &self.resonance);
+        children.push("gain", &self.gain);
+        children
+    }
+}
+
+impl Default for FilterBlock {
+    fn default() -> Self {
+        FilterBlock {
+            src: SignalSource::default(),
+            cutoff: SignalSource::new_anonymous(ConstantBlock::new(1000.0)),
+            resonance: SignalSource::new_anonymous(ConstantBlock::new(FRAC_1_SQRT_2)),
+            gain: SignalSource::new_anonymous(ConstantBlock::new(6.0)),
+            mode: FilterMode::Lowpass,
+            left: Biquad::default(),
+            right: Biquad::default(),
+        }
+    }
+}
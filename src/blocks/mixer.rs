@@ -0,0 +1,170 @@
+use std::any::Any;
+
+use crate::blocks::constant::ConstantBlock;
+use crate::blocks::stereo::PanLaw;
+use crate::blocks::{BlockType, SignalBlock, SignalBlockChildren, SignalSource};
+use crate::control::Control;
+use crate::graph::Context;
+
+pub struct MixerBlock {
+    channels: Vec<Channel>,
+    gain: SignalSource,
+    out_left: f32,
+    out_right: f32,
+}
+
+struct Channel {
+    input: SignalSource,
+    gain: SignalSource,
+    pan: SignalSource,
+    declared_mute: bool,
+    declared_solo: bool,
+    mute: bool,
+    solo: bool,
+}
+
+
+impl MixerBlock {
+    pub const PARAMETERS: &[&str] = &["input#", "in#", "gain#", "pan#", "mute#", "solo#", "gain", "master"];
+
+    fn channel(&mut self, n: usize) -> &mut Channel {
+        while self.channels.len() <= n {
+            self.channels.push(Channel::default());
+        }
+        &mut self.channels[n]
+    }
+
+    pub fn update_input(&mut self, n: usize, input: SignalSource) {
+        self.channel(n).input = input;
+    }
+
+    pub fn update_channel_gain(&mut self, n: usize, gain: SignalSource) {
+        self.channel(n).gain = gain;
+    }
+
+    pub fn update_pan(&mut self, n: usize, pan: SignalSource) {
+        self.channel(n).pan = pan;
+    }
+
+    pub fn update_mute(&mut self, n: usize, mute: bool) {
+        let channel = self.channel(n);
+        channel.declared_mute = mute;
+        channel.mute = mute;
+    }
+
+    pub fn update_solo(&mut self, n: usize, solo: bool) {
+        let channel = self.channel(n);
+        channel.declared_solo = solo;
+        channel.solo = solo;
+    }
+
+    pub fn update_gain(&mut self, gain: SignalSource) {
+        self.gain = gain;
+    }
+}
+
+
+impl SignalBlock for MixerBlock {
+    fn step(&mut self, ctx: &Context) {
+        self.gain.step(ctx);
+        for channel in &mut self.channels {
+            channel.input.step(ctx);
+            channel.gain.step(ctx);
+            channel.pan.step(ctx);
+        }
+
+        let any_solo = self.channels.iter().any(|c| c.solo);
+        let (mut left, mut right) = (0.0, 0.0);
+        for channel in &self.channels {
+            if channel.mute || (any_solo && !channel.solo) {
+                continue;
+            }
+
+            let (pan_left, pan_right) = PanLaw::EqualPower.normalized(channel.pan.get_mono());
+            let gain = channel.gain.get_mono();
+            left += channel.input.get_left() * gain * pan_left;
+            right += channel.input.get_right() * gain * pan_right;
+        }
+
+        let master = self.gain.get_mono();
+        self.out_left = left * master;
+        self.out_right = right * master;
+    }
+
+    fn get_mono(&self) -> f32 {
+        (self.out_left + self.out_right) / 2.0
+    }
+
+    fn get_left(&self) -> f32 {
+        self.out_left
+    }
+
+    fn get_right(&self) -> f32 {
+        self.out_right
+    }
+
+    fn block_type(&self) -> super::BlockType {
+        BlockType::Mixer
+    }
+
+    fn sync_from(&mut self, other: &dyn SignalBlock) {
+        // keep mutes and solos toggled at runtime unless the patch itself changed them
+        if let Some(other) = (other as &dyn Any).downcast_ref::<Self>() {
+            for (channel, other_channel) in self.channels.iter_mut().zip(&other.channels) {
+                if channel.declared_mute == other_channel.declared_mute {
+                    channel.mute = other_channel.mute;
+                }
+                if channel.declared_solo == other_channel.declared_solo {
+                    channel.solo = other_channel.solo;
+                }
+            }
+        }
+
+        self.sync_children_from(other);
+    }
+
+    fn control(&mut self, control: &Control) -> bool {
+        match control {
+            Control::Mute(n) if *n < self.channels.len() => self.channels[*n].mute ^= true,
+            Control::Solo(n) if *n < self.channels.len() => self.channels[*n].solo ^= true,
+            _ => return false,
+        }
+        true
+    }
+
+    fn children(&self) -> SignalBlockChildren<'_> {
+        let mut children = SignalBlockChildren::new();
+        children.push("gain", &self.gain);
+        for (n, channel) in self.channels.iter().enumerate() {
+            children.push(format!("input{n}"), &channel.input);
+            children.push(format!("gain{n}"), &channel.gain);
+            children.push(format!("pan{n}"), &channel.pan);
+        }
+        children
+    }
+}
+
+impl Default for MixerBlock {
+    fn default() -> Self {
+        MixerBlock {
+            channels: Vec::new(),
+            gain: SignalSource::new_anonymous(ConstantBlock::new(1.0)),
+            out_left: 0.0,
+            out_right: 0.0,
+        }
+    }
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Channel {
+            input: SignalSource::default(),
+            gain: SignalSource::new_anonymous(ConstantBlock::new(1.0)),
+            pan: SignalSource::default(),
+            declared_mute: false,
+            declared_solo: false,
+            mute: false,
+            solo: false,
+        }
+    }
+}
@@ -1,27 +1,69 @@
+use std::any::Any;
+use std::cell::RefCell;
 use std::collections::VecDeque;
-use std::iter;
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex, Weak};
 
 use crate::blocks::constant::ConstantBlock;
+use crate::control::Control;
+use crate::graph::{BlockBuffer, BlockId, Context, Frame};
 
 pub mod constant;
 pub mod oscillator;
 pub mod amplifier;
 pub mod stereo;
 pub mod sequencer;
+pub mod expression;
+pub mod envelope;
+pub mod filter;
+pub mod delay;
+pub mod reverb;
+pub mod noise;
+pub mod sample;
+pub mod mixer;
+pub mod transport;
 
-pub trait SignalBlock : Send {
-    fn step(&mut self);
+pub trait SignalBlock : Send + Any {
+    fn step(&mut self, ctx: &Context);
     fn get_mono(&self) -> f32;
     fn block_type(&self) -> BlockType;
 
+    /// Process a whole buffer of frames at once. Blocks that do not override
+    /// this are stepped one frame at a time.
+    fn process(&mut self, ctx: &Context, buffer: &mut BlockBuffer) {
+        for (i, ctx) in ctx.samples().enumerate() {
+            self.step(&ctx);
+            buffer.store(i, self);
+        }
+    }
+
     fn sync_from(&mut self, _other: &dyn SignalBlock);
 
+    /// Called once the graph is built, before the block is processed
+    fn prepare(&mut self, sample_rate: u32) {
+        self.prepare_children(sample_rate);
+    }
+
     fn sync_value(&self) -> f32 {
         0.0
     }
 
+    fn outputs(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn get_output(&self, _output: usize) -> f32 {
+        self.get_mono()
+    }
+
+    fn dependencies(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    fn control(&mut self, _control: &Control) -> bool {
+        false
+    }
+
     fn get_left(&self) -> f32 {
         self.get_mono()
     }
@@ -30,14 +72,41 @@ pub trait SignalBlock : Send {
         self.get_mono()
     }
 
-    fn children(&self) -> SignalBlockChildren {
-        SignalBlockChildren(VecDeque::new())
+    fn children(&self) -> SignalBlockChildren<'_> {
+        SignalBlockChildren::new()
     }
 
+    /// Named blocks this block reads from, including those referenced by its
+    /// anonymous children
+    fn references(&self) -> Vec<BlockId> {
+        let mut references = Vec::new();
+        for child in self.children().0 {
+            use SourceKind::*;
+            match &child.source.kind {
+                Anonymous(sb) => references.extend(sb.borrow().references()),
+                Named(id) | Output(id, _) => references.push(*id),
+            }
+        }
+        references
+    }
+
+    fn prepare_children(&self, sample_rate: u32) {
+        for (_, child) in self.children().anonymous() {
+            child.borrow_mut().prepare(sample_rate);
+        }
+    }
+
+    /// Migrate the state of anonymous children by their parameter slot, so the
+    /// identity of a nested block is its parent's name plus the path of slots
+    /// leading to it (e.g. `amp.src1/freq`). Named references are migrated by
+    /// the graph under their own name instead.
     fn sync_children_from(&self, other: &dyn SignalBlock) {
-        for (child, other_child) in iter::zip(self.children(), other.children()) {
-            let mut child = child.lock().unwrap();
-            let other_child = other_child.lock().unwrap();
+        let other_children = other.children();
+        for (slot, child) in self.children().anonymous() {
+            let Some((_, other_child)) = other_children.anonymous().find(|(s, _)| *s == slot) else { continue };
+
+            let mut child = child.borrow_mut();
+            let other_child = other_child.borrow();
             if child.block_type() == other_child.block_type() {
                 child.sync_from(&*other_child);
             }
@@ -45,9 +114,18 @@ pub trait SignalBlock : Send {
     }
 }
 
-pub enum SignalSource {
-    Anonymous(Arc<Mutex<dyn SignalBlock>>),
-    Named(Weak<Mutex<dyn SignalBlock>>),
+/// Input of a block. Anonymous blocks are owned and stepped by their consumer,
+/// named blocks and their outputs are read from the graph.
+pub struct SignalSource {
+    kind: SourceKind,
+    frame: Frame,
+    buffer: BlockBuffer,
+}
+
+enum SourceKind {
+    Anonymous(Box<RefCell<dyn SignalBlock>>),
+    Named(BlockId),
+    Output(BlockId, usize),
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -57,52 +135,166 @@ pub enum BlockType {
     Amplifier,
     Stereo,
     Sequencer,
+    Expression,
+    Envelope,
+    Filter,
+    Delay,
+    Reverb,
+    Noise,
+    Sample,
+    Mixer,
+    Transport,
 }
 
-pub struct SignalBlockChildren(VecDeque<Arc<Mutex<dyn SignalBlock>>>);
+pub struct SignalBlockChildren<'a>(VecDeque<SignalBlockChild<'a>>);
+
+struct SignalBlockChild<'a> {
+    slot: String,
+    source: &'a SignalSource,
+}
 
 
 impl SignalSource {
+    fn new(kind: SourceKind) -> Self {
+        SignalSource {
+            kind,
+            frame: Frame::default(),
+            buffer: BlockBuffer::new(0),
+        }
+    }
+
     pub fn new_anonymous(sb: impl SignalBlock + 'static) -> Self {
-        SignalSource::Anonymous(Arc::new(Mutex::new(sb)))
+        SignalSource::new(SourceKind::Anonymous(Box::new(RefCell::new(sb))))
     }
 
-    pub fn inner(&self) -> Arc<Mutex<dyn SignalBlock>> {
-        use SignalSource::*;
-        match self {
-            Anonymous(sb) => sb.clone(),
-            Named(weak) => weak.upgrade().unwrap(),
+    pub fn new_named(id: BlockId) -> Self {
+        SignalSource::new(SourceKind::Named(id))
+    }
+
+    pub fn new_output(id: BlockId, output: usize) -> Self {
+        SignalSource::new(SourceKind::Output(id, output))
+    }
+
+    pub fn step(&mut self, ctx: &Context) {
+        use SourceKind::*;
+        match &mut self.kind {
+            Anonymous(sb) => sb.get_mut().step(ctx),
+            Named(id) => self.frame = ctx.frame(*id),
+            Output(id, n) => {
+                let value = ctx.output(*id, *n);
+                self.frame = Frame { mono: value, left: value, right: value };
+            },
         }
     }
 
-    pub fn step(&self) {
-        use SignalSource::*;
-        match self {
-            Anonymous(sb) => sb.lock().unwrap().step(),
-            Named(_) => (),
+    /// Fill the buffer returned by `frames()` for the whole buffer of `ctx`
+    pub fn process(&mut self, ctx: &Context) {
+        self.buffer.set_len(ctx.len());
+
+        use SourceKind::*;
+        match &mut self.kind {
+            Anonymous(sb) => sb.get_mut().process(ctx, &mut self.buffer),
+            Named(id) => self.buffer.frames_mut().copy_from_slice(ctx.frames(*id)),
+            Output(id, n) => {
+                let values = ctx.output_buffer(*id, *n);
+                for (frame, &value) in self.buffer.frames_mut().iter_mut().zip(values) {
+                    *frame = Frame { mono: value, left: value, right: value };
+                }
+            },
+        }
+
+        // keep the per-sample getters in line with the end of the buffer
+        if let Some(last) = self.buffer.frames().last() {
+            self.frame = *last;
         }
     }
 
+    pub fn frames(&self) -> &[Frame] {
+        self.buffer.frames()
+    }
+
     pub fn get_mono(&self) -> f32 {
-        self.inner().lock().unwrap().get_mono()
+        match &self.kind {
+            SourceKind::Anonymous(sb) => sb.borrow().get_mono(),
+            _ => self.frame.mono,
+        }
     }
 
     pub fn get_left(&self) -> f32 {
-        self.inner().lock().unwrap().get_left()
+        match &self.kind {
+            SourceKind::Anonymous(sb) => sb.borrow().get_left(),
+            _ => self.frame.left,
+        }
     }
 
     pub fn get_right(&self) -> f32 {
-        self.inner().lock().unwrap().get_right()
+        match &self.kind {
+            SourceKind::Anonymous(sb) => sb.borrow().get_right(),
+            _ => self.frame.right,
+        }
+    }
+}
+
+impl BlockType {
+    pub fn name(self) -> &'static str {
+        use BlockType::*;
+        match self {
+            Constant => "constant",
+            Oscillator => "oscillator",
+            Amplifier => "amplifier",
+            Stereo => "stereo",
+            Sequencer => "sequencer",
+            Expression => "expression",
+            Envelope => "envelope",
+            Filter => "filter",
+            Delay => "delay",
+            Reverb => "reverb",
+            Noise => "noise",
+            Sample => "sample",
+            Mixer => "mixer",
+            Transport => "transport",
+        }
+    }
+
+    /// Keys accepted in the initializer of this block type, including their
+    /// aliases. A trailing `#` stands for a channel number.
+    pub fn parameters(self) -> &'static [&'static str] {
+        use BlockType::*;
+        match self {
+            Oscillator => oscillator::OscillatorBlock::PARAMETERS,
+            Amplifier => amplifier::AmplifierBlock::PARAMETERS,
+            Stereo => stereo::StereoBlock::PARAMETERS,
+            Sequencer => sequencer::SequencerBlock::PARAMETERS,
+            Envelope => envelope::EnvelopeBlock::PARAMETERS,
+            Filter => filter::FilterBlock::PARAMETERS,
+            Delay => delay::DelayBlock::PARAMETERS,
+            Reverb => reverb::ReverbBlock::PARAMETERS,
+            Noise => noise::NoiseBlock::PARAMETERS,
+            Sample => sample::SampleBlock::PARAMETERS,
+            Mixer => mixer::MixerBlock::PARAMETERS,
+            Constant | Expression | Transport => &[],
+        }
     }
 }
 
-impl SignalBlockChildren {
+impl<'a> SignalBlockChildren<'a> {
     fn new() -> Self {
         SignalBlockChildren(VecDeque::new())
     }
 
-    fn push(&mut self, child: Arc<Mutex<dyn SignalBlock>>) {
-        self.0.push_back(child);
+    fn push(&mut self, slot: impl Into<String>, source: &'a SignalSource) {
+        self.0.push_back(SignalBlockChild {
+            slot: slot.into(),
+            source,
+        });
+    }
+
+    /// Anonymous children together with the parameter slot they are bound to
+    pub fn anonymous(&self) -> impl Iterator<Item = (&str, &'a RefCell<dyn SignalBlock>)> {
+        self.0.iter().filter_map(|c| match &c.source.kind {
+            SourceKind::Anonymous(sb) => Some((c.slot.as_str(), &**sb)),
+            _ => None,
+        })
     }
 }
 
@@ -118,6 +310,13 @@ impl FromStr for BlockType {
             "amplifier" | "amp" => Ok(Amplifier),
             "stereo" => Ok(Stereo),
             "sequencer" | "seq" => Ok(Sequencer),
+            "envelope" | "env" | "adsr" => Ok(Envelope),
+            "filter" => Ok(Filter),
+            "delay" => Ok(Delay),
+            "reverb" | "verb" => Ok(Reverb),
+            "noise" => Ok(Noise),
+            "sample" | "smp" => Ok(Sample),
+            "mixer" | "mix" => Ok(Mixer),
             _ => Err(()),
         }
     }
@@ -125,14 +324,6 @@ impl FromStr for BlockType {
 
 impl Default for SignalSource {
     fn default() -> Self {
-        SignalSource::Anonymous(Arc::new(Mutex::new(ConstantBlock::default())))
-    }
-}
-
-impl Iterator for SignalBlockChildren {
-    type Item = Arc<Mutex<dyn SignalBlock>>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.0.pop_front()
+        SignalSource::new_anonymous(ConstantBlock::default())
     }
 }
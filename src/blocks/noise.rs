@@ -0,0 +1,117 @@
+use std::any::Any;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::blocks::{BlockType, SignalBlock};
+use crate::graph::Context;
+
+pub struct NoiseBlock {
+    color: NoiseColor,
+    seed: Option<u64>,
+    state: u64,
+    pink: [f32; 7],
+    brown: f32,
+    out: f32,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NoiseColor {
+    White,
+    Pink,
+    Brown,
+}
+
+
+impl NoiseBlock {
+    pub const PARAMETERS: &[&str] = &["color", "colour", "seed"];
+
+    pub fn update_color(&mut self, color: NoiseColor) {
+        self.color = color;
+    }
+
+    pub fn update_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+        self.state = Self::scramble(seed);
+    }
+
+    // splitmix64, so that similar seeds still lead to unrelated sequences
+    fn scramble(seed: u64) -> u64 {
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        (z ^ (z >> 31)).max(1)
+    }
+
+    // xorshift64, mapped to -1..1
+    fn white(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
+
+
+impl SignalBlock for NoiseBlock {
+    fn step(&mut self, _ctx: &Context) {
+        let white = self.white();
+
+        use NoiseColor::*;
+        self.out = match self.color {
+            White => white,
+            Pink => {
+                // Paul Kellet's refined pink noise filter
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.969 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                pink * 0.11
+            },
+            Brown => {
+                self.brown = (self.brown + 0.02 * white) / 1.02;
+                self.brown * 3.5
+            },
+        };
+    }
+
+    fn get_mono(&self) -> f32 {
+        self.out
+    }
+
+    fn block_type(&self) -> super::BlockType {
+        BlockType::Noise
+    }
+
+    fn sync_from(&mut self, other: &dyn SignalBlock) {
+        if let Some(other) = (other as &dyn Any).downcast_ref::<Self>() {
+            // a changed seed should restart the sequence rather than continue the old one
+            if self.seed == other.seed {
+                self.state = other.state;
+                self.pink = other.pink;
+                self.brown = other.brown;
+            }
+        }
+    }
+}
+
+impl Default for NoiseBlock {
+    fn default() -> Self {
+        let time_seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+
+        NoiseBlock {
+            color: NoiseColor::White,
+            seed: None,
+            state: Self::scramble(time_seed),
+            pink: [0.0; 7],
+            brown: 0.0,
+            out: 0.0,
+        }
+    }
+}
@@ -1,13 +1,22 @@
-use std::sync::*;
+use std::any::Any;
 use std::f32::consts::*;
 
 use crate::blocks::constant::ConstantBlock;
 use crate::blocks::{BlockType, SignalBlock, SignalBlockChildren, SignalSource};
+use crate::graph::{BlockBuffer, Context, Frame};
 
 pub struct OscillatorBlock {
     freq_source: SignalSource,
+    pulse_width: SignalSource,
+    phase_offset: SignalSource,
+    sync: SignalSource,
     phase: f32,
+    increment: f32,
     wave: Waveform,
+    polarity: Polarity,
+    naive: bool,
+    sync_high: bool,
+    value: f32,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -18,7 +27,15 @@ pub enum Waveform {
     Triangle,
 }
 
+#[derive(Copy, Clone, Debug)]
+pub enum Polarity {
+    Unipolar,
+    Bipolar,
+}
+
 impl OscillatorBlock {
+    pub const PARAMETERS: &[&str] = &["frequency", "freq", "wave", "waveform", "polarity", "naive", "pulse_width", "pw", "phase", "sync"];
+
     pub fn update_frequency(&mut self, freq_source: SignalSource) {
         self.freq_source = freq_source;
     }
@@ -26,15 +43,37 @@ impl OscillatorBlock {
     pub fn update_waveform(&mut self, wave: Waveform) {
         self.wave = wave;
     }
-}
 
+    pub fn update_polarity(&mut self, polarity: Polarity) {
+        self.polarity = polarity;
+    }
 
-impl SignalBlock for OscillatorBlock {
-    fn step(&mut self) {
-        self.freq_source.step();
+    pub fn update_naive(&mut self, naive: bool) {
+        self.naive = naive;
+    }
+
+    pub fn update_pulse_width(&mut self, pulse_width: SignalSource) {
+        self.pulse_width = pulse_width;
+    }
+
+    pub fn update_phase(&mut self, phase_offset: SignalSource) {
+        self.phase_offset = phase_offset;
+    }
+
+    pub fn update_sync(&mut self, sync: SignalSource) {
+        self.sync = sync;
+    }
+
+    fn advance(&mut self, sample_rate: f32, freq: f32, sync: f32) {
+        self.increment = freq / sample_rate;
+        self.phase += self.increment;
 
-        let freq = self.freq_source.inner().lock().unwrap().get_mono();
-        self.phase += freq / (crate::SAMPLE_RATE as f32);
+        // hard sync: restart the cycle on a rising edge
+        let sync_high = sync > 0.0;
+        if sync_high && !self.sync_high {
+            self.phase = 0.0;
+        }
+        self.sync_high = sync_high;
 
         // limit phase between 0 and 2*PI to avoid inaccuracies
         while self.phase > 1.0 {
@@ -45,15 +84,103 @@ impl SignalBlock for OscillatorBlock {
         }
     }
 
-    fn get_mono(&self) -> f32 {
-        let fract = self.phase.fract().abs();
+    fn waveform(&self, phase_offset: f32, pulse_width: f32) -> f32 {
+        let phase = (self.phase + phase_offset).rem_euclid(1.0);
         use Waveform::*;
-        match self.wave {
-            Sinus => f32::sin(self.phase * 2.0 * PI),
-            Sawtooth => 1.0 - fract,
-            Square => if fract < 0.5 { 1.0 } else { 0.0 }
-            Triangle => if fract < 0.5 { 2.0 * fract } else { 1.0 - 2.0 * fract }
+        let bipolar = match self.wave {
+            Sinus => f32::sin(phase * 2.0 * PI),
+            Sawtooth => {
+                let naive = 1.0 - 2.0 * phase;
+                naive + self.smooth(phase, 0.0, 2.0, 0.0)
+            },
+            Square => {
+                let pw = pulse_width.clamp(0.01, 0.99);
+                let naive = if phase < pw { 1.0 } else { -1.0 };
+                naive + self.smooth(phase, 0.0, 2.0, 0.0) + self.smooth(phase, pw, -2.0, 0.0)
+            },
+            Triangle => {
+                let naive = if phase < 0.25 {
+                    4.0 * phase
+                } else if phase < 0.75 {
+                    2.0 - 4.0 * phase
+                } else {
+                    4.0 * phase - 4.0
+                };
+                naive + self.smooth(phase, 0.25, 0.0, -8.0) + self.smooth(phase, 0.75, 0.0, 8.0)
+            },
+        };
+
+        match self.polarity {
+            Polarity::Bipolar => bipolar,
+            Polarity::Unipolar => (bipolar + 1.0) / 2.0,
+        }
+    }
+
+    // correction around a discontinuity at phase `at`, which jumps by `step` in value and by `slope` in derivative
+    fn smooth(&self, phase: f32, at: f32, step: f32, slope: f32) -> f32 {
+        if self.naive {
+            return 0.0;
         }
+
+        let t = (phase - at).rem_euclid(1.0);
+        let dt = self.increment.abs().min(0.5);
+        step / 2.0 * poly_blep(t, dt) + slope * dt / 2.0 * poly_blamp(t, dt)
+    }
+}
+
+// polynomial approximation of the residual between a band-limited and a naive step
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        2.0 * t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+// integrated PolyBLEP, for discontinuities in the first derivative
+fn poly_blamp(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt - 1.0;
+        -t * t * t / 3.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt + 1.0;
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
+
+
+impl SignalBlock for OscillatorBlock {
+    fn step(&mut self, ctx: &Context) {
+        self.freq_source.step(ctx);
+        self.pulse_width.step(ctx);
+        self.phase_offset.step(ctx);
+        self.sync.step(ctx);
+
+        self.advance(ctx.sample_rate(), self.freq_source.get_mono(), self.sync.get_mono());
+        self.value = self.waveform(self.phase_offset.get_mono(), self.pulse_width.get_mono());
+    }
+
+    fn process(&mut self, ctx: &Context, buffer: &mut BlockBuffer) {
+        self.freq_source.process(ctx);
+        self.pulse_width.process(ctx);
+        self.phase_offset.process(ctx);
+        self.sync.process(ctx);
+
+        for (i, frame) in buffer.frames_mut().iter_mut().enumerate() {
+            self.advance(ctx.sample_rate(), self.freq_source.frames()[i].mono, self.sync.frames()[i].mono);
+            self.value = self.waveform(self.phase_offset.frames()[i].mono, self.pulse_width.frames()[i].mono);
+            *frame = Frame { mono: self.value, left: self.value, right: self.value };
+        }
+    }
+
+    fn get_mono(&self) -> f32 {
+        self.value
     }
 
     fn sync_from(&mut self, other: &dyn SignalBlock) {
@@ -61,6 +188,10 @@ impl SignalBlock for OscillatorBlock {
             self.phase = other.sync_value();
         }
 
+        if let Some(other) = (other as &dyn Any).downcast_ref::<Self>() {
+            self.sync_high = other.sync_high;
+        }
+
         self.sync_children_from(other);
     }
 
@@ -72,9 +203,12 @@ impl SignalBlock for OscillatorBlock {
         self.phase
     }
 
-    fn children(&self) -> SignalBlockChildren {
+    fn children(&self) -> SignalBlockChildren<'_> {
         let mut children = SignalBlockChildren::new();
-        children.push(self.freq_source.inner());
+        children.push("freq", &self.freq_source);
+        children.push("pulse_width", &self.pulse_width);
+        children.push("phase", &self.phase_offset);
+        children.push("sync", &self.sync);
         children
     }
 }
@@ -83,9 +217,84 @@ impl SignalBlock for OscillatorBlock {
 impl Default for OscillatorBlock {
     fn default() -> Self {
         OscillatorBlock {
-            freq_source: SignalSource::Anonymous(Arc::new(Mutex::new(ConstantBlock::new(440.0)))),
+            freq_source: SignalSource::new_anonymous(ConstantBlock::new(440.0)),
+            pulse_width: SignalSource::new_anonymous(ConstantBlock::new(0.5)),
+            phase_offset: SignalSource::default(),
+            sync: SignalSource::default(),
             phase: 0.0,
+            increment: 0.0,
             wave: Waveform::Sinus,
+            polarity: Polarity::Bipolar,
+            naive: false,
+            sync_high: false,
+            value: 0.0,
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{Graph, BUFFER_SIZE};
+    use crate::transport::Transport;
+
+    const SAMPLE_RATE: u32 = 44100;
+    const FREQ: f32 = 3520.0;
+
+    fn render_saw(naive: bool) -> Vec<f32> {
+        let mut osc = OscillatorBlock::default();
+        osc.update_frequency(SignalSource::new_anonymous(ConstantBlock::new(FREQ)));
+        osc.update_waveform(Waveform::Sawtooth);
+        osc.update_naive(naive);
+
+        let mut graph = Graph::new();
+        let id = graph.register_block("saw".to_owned(), osc);
+        graph.set_output(id);
+        graph.build_schedule();
+        graph.prepare(SAMPLE_RATE);
+
+        let transport = Transport::new(120.0, 4, SAMPLE_RATE);
+        let mut samples = Vec::new();
+        while samples.len() < SAMPLE_RATE as usize {
+            graph.process(&transport, BUFFER_SIZE);
+            samples.extend((0..BUFFER_SIZE).map(|i| graph.output(i).mono));
+        }
+        samples.truncate(SAMPLE_RATE as usize);
+        samples
+    }
+
+    // energy of one DFT bin, evaluated with the Goertzel algorithm
+    fn bin_energy(samples: &[f32], bin: usize) -> f64 {
+        let w = 2.0 * std::f64::consts::PI * bin as f64 / samples.len() as f64;
+        let (mut s1, mut s2) = (0.0, 0.0);
+        for &x in samples {
+            let s = x as f64 + 2.0 * w.cos() * s1 - s2;
+            s2 = s1;
+            s1 = s;
+        }
+        let power = s1 * s1 + s2 * s2 - 2.0 * w.cos() * s1 * s2;
+        // a real signal puts the same energy into the mirrored bin
+        2.0 * power / samples.len() as f64
+    }
+
+    // share of the energy that is not at a harmonic of the fundamental, i.e. aliasing, in dB
+    fn inharmonic_db(samples: &[f32]) -> f64 {
+        // one second of audio, so bins are 1 Hz apart and every harmonic has its own bin
+        let total: f64 = samples.iter().map(|&x| (x as f64).powi(2)).sum();
+        let harmonic: f64 = (1..)
+            .map(|k| k * FREQ as usize)
+            .take_while(|&f| f < SAMPLE_RATE as usize / 2)
+            .map(|f| bin_energy(samples, f))
+            .sum();
+        10.0 * ((total - harmonic).max(0.0) / total).log10()
+    }
+
+    #[test]
+    fn polyblep_suppresses_aliasing() {
+        let blep = inharmonic_db(&render_saw(false));
+        let naive = inharmonic_db(&render_saw(true));
+        assert!(blep < -20.0, "band-limited saw aliases at {blep:.1} dB");
+        assert!(naive - blep > 10.0, "band-limited {blep:.1} dB vs naive {naive:.1} dB");
+    }
+}
@@ -0,0 +1,245 @@
+use std::any::Any;
+
+use crate::blocks::constant::ConstantBlock;
+use crate::blocks::{BlockType, SignalBlock, SignalBlockChildren, SignalSource};
+use crate::graph::Context;
+
+// tunings of the original Freeverb, given in samples at 44.1kHz
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+
+const FIXED_GAIN: f32 = 0.015;
+const SCALE_WET: f32 = 1.0;
+const SCALE_DAMPING: f32 = 0.4;
+const SCALE_ROOM: f32 = 0.28;
+const OFFSET_ROOM: f32 = 0.7;
+const ALLPASS_FEEDBACK: f32 = 0.5;
+
+pub struct ReverbBlock {
+    src: SignalSource,
+    size: SignalSource,
+    damping: SignalSource,
+    width: SignalSource,
+    mix: SignalSource,
+    left: ReverbChannel,
+    right: ReverbChannel,
+    out_left: f32,
+    out_right: f32,
+}
+
+struct ReverbChannel {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+struct Comb {
+    buffer: Vec<f32>,
+    pos: usize,
+    filter_store: f32,
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    pos: usize,
+}
+
+
+impl ReverbBlock {
+    pub const PARAMETERS: &[&str] = &["source", "src", "size", "room", "damping", "damp", "width", "mix"];
+
+    pub fn update_source(&mut self, src: SignalSource) {
+        self.src = src;
+    }
+
+    pub fn update_size(&mut self, size: SignalSource) {
+        self.size = size;
+    }
+
+    pub fn update_damping(&mut self, damping: SignalSource) {
+        self.damping = damping;
+    }
+
+    pub fn update_width(&mut self, width: SignalSource) {
+        self.width = width;
+    }
+
+    pub fn update_mix(&mut self, mix: SignalSource) {
+        self.mix = mix;
+    }
+}
+
+impl ReverbChannel {
+    fn new(spread: usize, sample_rate: u32) -> Self {
+        let scale = |tuning: usize| (tuning + spread) * sample_rate as usize / 44100;
+        ReverbChannel {
+            combs: COMB_TUNINGS.iter().map(|t| Comb::new(scale(*t))).collect(),
+            allpasses: ALLPASS_TUNINGS.iter().map(|t| Allpass::new(scale(*t))).collect(),
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let mut out = self.combs.iter_mut()
+            .map(|c| c.process(input, feedback, damping))
+            .sum();
+        for allpass in &mut self.allpasses {
+            out = allpass.process(out);
+        }
+        out
+    }
+}
+
+impl Comb {
+    fn new(len: usize) -> Self {
+        Comb { buffer: vec![0.0; len.max(1)], pos: 0, filter_store: 0.0 }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let out = self.buffer[self.pos];
+        self.filter_store = out * (1.0 - damping) + self.filter_store * damping;
+        self.buffer[self.pos] = input + self.filter_store * feedback;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        out
+    }
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Allpass { buffer: vec![0.0; len.max(1)], pos: 0 }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.pos];
+        self.buffer[self.pos] = input + buffered * ALLPASS_FEEDBACK;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        buffered - input
+    }
+}
+
+// the states are copied on the audio thread during a reload, so reuse the buffers instead of reallocating them
+impl Clone for ReverbChannel {
+    fn clone(&self) -> Self {
+        ReverbChannel { combs: self.combs.clone(), allpasses: self.allpasses.clone() }
+    }
+
+    fn clone_from(&mut self, other: &Self) {
+        self.combs.clone_from(&other.combs);
+        self.allpasses.clone_from(&other.allpasses);
+    }
+}
+
+impl Clone for Comb {
+    fn clone(&self) -> Self {
+        Comb { buffer: self.buffer.clone(), pos: self.pos, filter_store: self.filter_store }
+    }
+
+    fn clone_from(&mut self, other: &Self) {
+        if self.buffer.len() == other.buffer.len() {
+            self.buffer.copy_from_slice(&other.buffer);
+        } else {
+            self.buffer.clone_from(&other.buffer);
+        }
+        self.pos = other.pos;
+        self.filter_store = other.filter_store;
+    }
+}
+
+impl Clone for Allpass {
+    fn clone(&self) -> Self {
+        Allpass { buffer: self.buffer.clone(), pos: self.pos }
+    }
+
+    fn clone_from(&mut self, other: &Self) {
+        if self.buffer.len() == other.buffer.len() {
+            self.buffer.copy_from_slice(&other.buffer);
+        } else {
+            self.buffer.clone_from(&other.buffer);
+        }
+        self.pos = other.pos;
+    }
+}
+
+
+impl SignalBlock for ReverbBlock {
+    fn step(&mut self, ctx: &Context) {
+        self.src.step(ctx);
+        self.size.step(ctx);
+        self.damping.step(ctx);
+        self.width.step(ctx);
+        self.mix.step(ctx);
+
+        let feedback = self.size.get_mono().clamp(0.0, 1.0) * SCALE_ROOM + OFFSET_ROOM;
+        let damping = self.damping.get_mono().clamp(0.0, 1.0) * SCALE_DAMPING;
+        let width = self.width.get_mono().clamp(0.0, 1.0);
+        let mix = self.mix.get_mono().clamp(0.0, 1.0);
+
+        let dry_left = self.src.get_left();
+        let dry_right = self.src.get_right();
+        let input = (dry_left + dry_right) * FIXED_GAIN;
+
+        let wet_left = self.left.process(input, feedback, damping);
+        let wet_right = self.right.process(input, feedback, damping);
+
+        let wet1 = mix * SCALE_WET * (width / 2.0 + 0.5);
+        let wet2 = mix * SCALE_WET * ((1.0 - width) / 2.0);
+        self.out_left = wet_left * wet1 + wet_right * wet2 + dry_left * (1.0 - mix);
+        self.out_right = wet_right * wet1 + wet_left * wet2 + dry_right * (1.0 - mix);
+    }
+
+    fn get_mono(&self) -> f32 {
+        (self.out_left + self.out_right) / 2.0
+    }
+
+    fn get_left(&self) -> f32 {
+        self.out_left
+    }
+
+    fn get_right(&self) -> f32 {
+        self.out_right
+    }
+
+    fn block_type(&self) -> super::BlockType {
+        BlockType::Reverb
+    }
+
+    fn prepare(&mut self, sample_rate: u32) {
+        self.left = ReverbChannel::new(0, sample_rate);
+        self.right = ReverbChannel::new(STEREO_SPREAD, sample_rate);
+        self.prepare_children(sample_rate);
+    }
+
+    fn sync_from(&mut self, other: &dyn SignalBlock) {
+        if let Some(other) = (other as &dyn Any).downcast_ref::<Self>() {
+            self.left.clone_from(&other.left);
+            self.right.clone_from(&other.right);
+        }
+
+        self.sync_children_from(other);
+    }
+
+    fn children(&self) -> SignalBlockChildren<'_> {
+        let mut children = SignalBlockChildren::new();
+        children.push("src", &self.src);
+        children.push("size", &self.size);
+        children.push("damping", &self.damping);
+        children.push("width", &self.width);
+        children.push("mix", &self.mix);
+        children
+    }
+}
+
+impl Default for ReverbBlock {
+    fn default() -> Self {
+        ReverbBlock {
+            src: SignalSource::default(),
+            size: SignalSource::new_anonymous(ConstantBlock::new(0.5)),
+            damping: SignalSource::new_anonymous(ConstantBlock::new(0.5)),
+            width: SignalSource::new_anonymous(ConstantBlock::new(1.0)),
+            mix: SignalSource::new_anonymous(ConstantBlock::new(0.3)),
+            left: ReverbChannel::new(0, 0),
+            right: ReverbChannel::new(STEREO_SPREAD, 0),
+            out_left: 0.0,
+            out_right: 0.0,
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::any::Any;
+use std::path::{Path, PathBuf};
+
+use hound::{SampleFormat, WavReader};
+
+use crate::blocks::constant::ConstantBlock;
+use crate::blocks::{BlockType, SignalBlock, SignalBlockChildren, SignalSource};
+use crate::error::HarmoniconError;
+use crate::graph::Context;
+
+pub struct SampleBlock {
+    file: PathBuf,
+    left: Vec<f32>,
+    right: Vec<f32>,
+    sample_rate: u32,
+    trig: SignalSource,
+    rate: SignalSource,
+    start: SignalSource,
+    end: SignalSource,
+    looping: SignalSource,
+    pos: f32,
+    playing: bool,
+    trig_high: bool,
+}
+
+
+impl SampleBlock {
+    pub const PARAMETERS: &[&str] = &["file", "trigger", "trig", "rate", "start", "end", "loop"];
+
+    pub fn load(&mut self, file: &Path) -> crate::Result<()> {
+        let load_err = |e| HarmoniconError::SampleLoad(file.display().to_string(), e);
+        let mut reader = WavReader::open(file).map_err(load_err)?;
+        let spec = reader.spec();
+
+        let samples: Vec<f32> = match spec.sample_format {
+            SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>(),
+            SampleFormat::Int => {
+                let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+                reader.samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 / scale))
+                    .collect::<Result<_, _>>()
+            },
+        }.map_err(load_err)?;
+
+        let channels = spec.channels.max(1) as usize;
+        self.left = samples.iter().step_by(channels).copied().collect();
+        self.right = if channels > 1 {
+            samples.iter().skip(1).step_by(channels).copied().collect()
+        } else {
+            self.left.clone()
+        };
+        self.sample_rate = spec.sample_rate;
+        self.file = file.to_owned();
+
+        Ok(())
+    }
+
+    pub fn update_trigger(&mut self, trig: SignalSource) {
+        self.trig = trig;
+    }
+
+    pub fn update_rate(&mut self, rate: SignalSource) {
+        self.rate = rate;
+    }
+
+    pub fn update_start(&mut self, start: SignalSource) {
+        self.start = start;
+    }
+
+    pub fn update_end(&mut self, end: SignalSource) {
+        self.end = end;
+    }
+
+    pub fn update_loop(&mut self, looping: SignalSource) {
+        self.looping = looping;
+    }
+
+    fn bounds(&self) -> (f32, f32) {
+        let len = self.left.len() as f32;
+        let start = self.start.get_mono().clamp(0.0, 1.0) * len;
+        let end = self.end.get_mono().clamp(0.0, 1.0) * len;
+        (start.min(end), start.max(end))
+    }
+
+    fn read(buffer: &[f32], pos: f32) -> f32 {
+        let i = pos as usize;
+        let fract = pos.fract();
+        let a = buffer.get(i).copied().unwrap_or(0.0);
+        let b = buffer.get(i + 1).copied().unwrap_or(0.0);
+        a * (1.0 - fract) + b * fract
+    }
+}
+
+
+impl SignalBlock for SampleBlock {
+    fn step(&mut self, ctx: &Context) {
+        self.trig.step(ctx);
+        self.rate.step(ctx);
+        self.start.step(ctx);
+        self.end.step(ctx);
+        self.looping.step(ctx);
+
+        let (start, end) = self.bounds();
+        let rate = self.rate.get_mono();
+
+        let trig_high = self.trig.get_mono() > 0.0;
+        if trig_high && !self.trig_high {
+            self.playing = true;
+            self.pos = if rate < 0.0 { end - 1.0 } else { start };
+        } else if self.playing {
+            // resample to the output rate on the fly
+            self.pos += rate * self.sample_rate as f32 / ctx.sample_rate();
+        }
+        self.trig_high = trig_high;
+
+        if self.playing && (self.pos >= end || self.pos < start) {
+            let range = end - start;
+            if self.looping.get_mono() > 0.0 && range >= 1.0 {
+                self.pos = start + (self.pos - start).rem_euclid(range);
+            } else {
+                self.playing = false;
+            }
+        }
+    }
+
+    fn get_mono(&self) -> f32 {
+        (self.get_left() + self.get_right()) / 2.0
+    }
+
+    fn get_left(&self) -> f32 {
+        if self.playing {
+            Self::read(&self.left, self.pos)
+        } else {
+            0.0
+        }
+    }
+
+    fn get_right(&self) -> f32 {
+        if self.playing {
+            Self::read(&self.right, self.pos)
+        } else {
+            0.0
+        }
+    }
+
+    fn block_type(&self) -> super::BlockType {
+        BlockType::Sample
+    }
+
+    fn sync_from(&mut self, other: &dyn SignalBlock) {
+        if let Some(other) = (other as &dyn Any).downcast_ref::<Self>() {
+            self.pos = other.pos;
+            self.playing = other.playing;
+            self.trig_high = other.trig_high;
+        }
+
+        self.sync_children_from(other);
+    }
+
+    fn dependencies(&self) -> Vec<PathBuf> {
+        if self.file.as_os_str().is_empty() {
+            Vec::new()
+        } else {
+            vec![self.file.clone()]
+        }
+    }
+
+    fn children(&self) -> SignalBlockChildren<'_> {
+        let mut children = SignalBlockChildren::new();
+        children.push("trigger", &self.trig);
+        children.push("rate", &self.rate);
+        children.push("start", &self.start);
+        children.push("end", &self.end);
+        children.push("loop", &self.looping);
+        children
+    }
+}
+
+impl Default for SampleBlock {
+    fn default() -> Self {
+        SampleBlock {
+            file: PathBuf::new(),
+            left: Vec::new(),
+            right: Vec::new(),
+            sample_rate: crate::DEFAULT_SAMPLE_RATE,
+            trig: SignalSource::new_anonymous(ConstantBlock::new(1.0)),
+            rate: SignalSource::new_anonymous(ConstantBlock::new(1.0)),
+            start: SignalSource::new_anonymous(ConstantBlock::new(0.0)),
+            end: SignalSource::new_anonymous(ConstantBlock::new(1.0)),
+            looping: SignalSource::new_anonymous(ConstantBlock::new(0.0)),
+            pos: 0.0,
+            playing: false,
+            trig_high: false,
+        }
+    }
+}
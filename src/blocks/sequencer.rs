@@ -1,42 +1,89 @@
+use std::any::Any;
+
 use crate::blocks::constant::ConstantBlock;
 use crate::blocks::{BlockType, SignalBlock, SignalBlockChildren, SignalSource};
 use crate::note::Note;
+use crate::graph::Context;
 
 pub struct SequencerBlock {
     sequence: Vec<Note>,
-    bpm: SignalSource,
+    velocities: Vec<f32>,
+    bpm: Option<SignalSource>,
     spacing: SignalSource,
     progress: f32,
+    step: usize,
+    gate: bool,
+    trig: bool,
 }
 
 
 impl SequencerBlock {
+    pub const PARAMETERS: &[&str] = &["sequence", "seq", "velocity", "vel", "bpm", "spacing"];
+    const OUTPUTS: &[&str] = &["freq", "gate", "trig", "step", "velocity"];
+
     pub fn update_sequence(&mut self, seq: Vec<Note>) {
         self.sequence = seq
     }
 
+    pub fn update_velocities(&mut self, velocities: Vec<f32>) {
+        self.velocities = velocities
+    }
+
     pub fn update_bpm(&mut self, bpm: SignalSource) {
-        self.bpm = bpm
+        self.bpm = Some(bpm)
     }
 
     pub fn update_spacing(&mut self, spacing: SignalSource) {
         self.spacing = spacing
     }
+
+    fn in_spacing(&self) -> bool {
+        self.spacing.get_mono() >= 0.05
+            && (self.progress.round()  - self.progress).abs() < self.spacing.get_mono() / 2.0
+    }
+
+    fn velocity(&self) -> f32 {
+        if self.velocities.is_empty() {
+            1.0
+        } else {
+            self.velocities[self.step % self.velocities.len()]
+        }
+    }
 }
 
 
 impl SignalBlock for SequencerBlock {
-    fn step(&mut self) {
-        self.progress += self.bpm.get_mono() / (crate::SAMPLE_RATE as f32 * 60.0);
-        if self.progress > self.sequence.len() as f32 {
-            self.progress -= self.sequence.len() as f32
+    fn step(&mut self, ctx: &Context) {
+        self.spacing.step(ctx);
+
+        // without an explicit bpm the sequencer is locked to the global transport
+        match &mut self.bpm {
+            Some(bpm) => {
+                bpm.step(ctx);
+                self.progress += bpm.get_mono() / (ctx.sample_rate() * 60.0);
+                if self.progress > self.sequence.len() as f32 {
+                    self.progress -= self.sequence.len() as f32
+                }
+            },
+            None => self.progress = (ctx.transport.beat() % self.sequence.len().max(1) as f64) as f32,
         }
+
+        if self.sequence.is_empty() {
+            self.step = 0;
+            self.gate = false;
+            self.trig = false;
+            return;
+        }
+
+        let step = self.progress as usize % self.sequence.len();
+        let gate = !self.in_spacing() && self.sequence[step].frequency() > 0.0;
+        self.trig = gate && (!self.gate || step != self.step);
+        self.gate = gate;
+        self.step = step;
     }
 
     fn get_mono(&self) -> f32 {
-        let in_spacing = self.spacing.get_mono() >= 0.05
-            && (self.progress.round()  - self.progress).abs() < self.spacing.get_mono() / 2.0;
-        if in_spacing || self.sequence.len() == 0 {
+        if self.in_spacing() || self.sequence.is_empty() {
             0.0
         } else {
             self.sequence[self.progress as usize % self.sequence.len()].frequency()
@@ -48,17 +95,38 @@ impl SignalBlock for SequencerBlock {
     }
 
     fn sync_from(&mut self, other: &dyn SignalBlock) {
-        self.progress = other.sync_value()
+        self.progress = other.sync_value();
+
+        if let Some(other) = (other as &dyn Any).downcast_ref::<Self>() {
+            self.step = other.step;
+            self.gate = other.gate;
+        }
     }
 
     fn sync_value(&self) -> f32 {
         self.progress
     }
 
-    fn children(&self) -> SignalBlockChildren {
+    fn outputs(&self) -> &'static [&'static str] {
+        Self::OUTPUTS
+    }
+
+    fn get_output(&self, output: usize) -> f32 {
+        match output {
+            1 => self.gate as u8 as f32,
+            2 => self.trig as u8 as f32,
+            3 => self.step as f32,
+            4 => self.velocity(),
+            _ => self.get_mono(),
+        }
+    }
+
+    fn children(&self) -> SignalBlockChildren<'_> {
         let mut children = SignalBlockChildren::new();
-        children.push(self.bpm.inner());
-        children.push(self.spacing.inner());
+        children.push("spacing", &self.spacing);
+        if let Some(bpm) = &self.bpm {
+            children.push("bpm", bpm);
+        }
         children
     }
 }
@@ -67,9 +135,13 @@ impl Default for SequencerBlock {
     fn default() -> Self {
         SequencerBlock {
             sequence: Vec::new(),
-            bpm: SignalSource::new_anonymous(ConstantBlock::new(120.0)),
+            velocities: Vec::new(),
+            bpm: None,
             progress: 0.0,
             spacing: SignalSource::new_anonymous(ConstantBlock::new(0.0)),
+            step: 0,
+            gate: false,
+            trig: false,
         }
     }
 }
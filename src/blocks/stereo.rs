@@ -1,15 +1,30 @@
+use std::f32::consts::*;
 
+use crate::blocks::constant::ConstantBlock;
 use crate::blocks::{BlockType, SignalBlock, SignalBlockChildren, SignalSource};
+use crate::graph::Context;
 
-#[derive(Default)]
 pub struct StereoBlock {
     left: SignalSource,
     right: SignalSource,
-    shift: SignalSource,
+    src: Option<SignalSource>,
+    pan: SignalSource,
+    width: SignalSource,
+    law: PanLaw,
+    out_left: f32,
+    out_right: f32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum PanLaw {
+    Linear,
+    EqualPower,
 }
 
 
 impl StereoBlock {
+    pub const PARAMETERS: &[&str] = &["source", "src", "left", "l", "right", "r", "pan", "shift", "s", "width", "w", "law"];
+
     pub fn update_left(&mut self, left: SignalSource) {
         self.left = left;
     }
@@ -18,42 +33,95 @@ impl StereoBlock {
         self.right = right;
     }
 
-    pub fn update_shift(&mut self, shift: SignalSource) {
-        self.shift = shift;
+    pub fn update_source(&mut self, src: SignalSource) {
+        self.src = Some(src);
+    }
+
+    pub fn update_pan(&mut self, pan: SignalSource) {
+        self.pan = pan;
+    }
+
+    pub fn update_width(&mut self, width: SignalSource) {
+        self.width = width;
+    }
+
+    pub fn update_law(&mut self, law: PanLaw) {
+        self.law = law;
+    }
+}
+
+impl PanLaw {
+    pub fn gains(self, pan: f32) -> (f32, f32) {
+        let x = (pan.clamp(-1.0, 1.0) + 1.0) / 2.0;
+        match self {
+            PanLaw::Linear => (1.0 - x, x),
+            PanLaw::EqualPower => ((x * FRAC_PI_2).cos(), (x * FRAC_PI_2).sin()),
+        }
+    }
+
+    /// Gains normalised to unity in the centre, for panning signals that are
+    /// already stereo, where pan acts as a balance control that only ever
+    /// attenuates the side it moves away from
+    pub fn normalized(self, pan: f32) -> (f32, f32) {
+        let (left, right) = self.gains(pan);
+        let centre = match self {
+            PanLaw::Linear => 2.0,
+            PanLaw::EqualPower => SQRT_2,
+        };
+        ((left * centre).min(1.0), (right * centre).min(1.0))
     }
 }
 
 
 impl SignalBlock for StereoBlock {
-    fn step(&mut self) {
-        self.left.step();
-        self.right.step();
-        self.shift.step();
+    fn step(&mut self, ctx: &Context) {
+        self.left.step(ctx);
+        self.right.step(ctx);
+        if let Some(src) = &mut self.src {
+            src.step(ctx);
+        }
+        self.pan.step(ctx);
+        self.width.step(ctx);
+
+        let pan = self.pan.get_mono();
+        let ((left, right), (gain_left, gain_right)) = match &self.src {
+            Some(src) => ((src.get_mono(), src.get_mono()), self.law.gains(pan)),
+            None => ((self.left.get_left(), self.right.get_right()), self.law.normalized(pan)),
+        };
+
+        // mid/side processing
+        let mid = (left + right) / 2.0;
+        let side = (left - right) / 2.0 * self.width.get_mono().max(0.0);
+
+        self.out_left = (mid + side) * gain_left;
+        self.out_right = (mid - side) * gain_right;
     }
 
     fn get_mono(&self) -> f32 {
-        self.left.get_mono() + self.right.get_mono() / 2.0
+        (self.out_left + self.out_right) / 2.0
     }
 
     fn get_left(&self) -> f32 {
-        let shift = (self.shift.get_mono() + 1.0) / 2.0;
-        self.left.get_left() * (1.0 - shift)
+        self.out_left
     }
 
     fn get_right(&self) -> f32 {
-        let shift = (self.shift.get_mono() + 1.0) / 2.0;
-        self.left.get_right() * shift
+        self.out_right
     }
 
     fn block_type(&self) -> super::BlockType {
         BlockType::Stereo
     }
 
-    fn children(&self) -> SignalBlockChildren {
+    fn children(&self) -> SignalBlockChildren<'_> {
         let mut children = SignalBlockChildren::new();
-        children.push(self.left.inner());
-        children.push(self.right.inner());
-        children.push(self.shift.inner());
+        children.push("left", &self.left);
+        children.push("right", &self.right);
+        children.push("pan", &self.pan);
+        children.push("width", &self.width);
+        if let Some(src) = &self.src {
+            children.push("src", src);
+        }
         children
     }
 
@@ -61,3 +129,43 @@ impl SignalBlock for StereoBlock {
         self.sync_children_from(other);
     }
 }
+
+impl Default for StereoBlock {
+    fn default() -> Self {
+        StereoBlock {
+            left: SignalSource::default(),
+            right: SignalSource::default(),
+            src: None,
+            pan: SignalSource::default(),
+            width: SignalSource::new_anonymous(ConstantBlock::new(1.0)),
+            law: PanLaw::EqualPower,
+            out_left: 0.0,
+            out_right: 0.0,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_gains_are_unity_in_the_centre() {
+        for law in [PanLaw::Linear, PanLaw::EqualPower] {
+            let (left, right) = law.normalized(0.0);
+            assert!((left - 1.0).abs() < 1e-6 && (right - 1.0).abs() < 1e-6, "{law:?}: {left}, {right}");
+        }
+    }
+
+    #[test]
+    fn normalized_gains_never_exceed_unity() {
+        for law in [PanLaw::Linear, PanLaw::EqualPower] {
+            for pan in [-1.0, -0.5, 0.5, 1.0] {
+                let (left, right) = law.normalized(pan);
+                assert!(left <= 1.0 && right <= 1.0, "{law:?} at {pan}: {left}, {right}");
+            }
+            assert_eq!(law.normalized(-1.0), (1.0, 0.0), "{law:?}");
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use crate::blocks::{BlockType, SignalBlock};
+use crate::graph::Context;
+
+#[derive(Default)]
+pub struct TransportBlock {
+    beat: f32,
+    bar: f32,
+    phase: f32,
+    bpm: f32,
+}
+
+
+impl TransportBlock {
+    const OUTPUTS: &[&str] = &["beat", "bar", "phase", "bpm"];
+}
+
+
+impl SignalBlock for TransportBlock {
+    fn step(&mut self, ctx: &Context) {
+        self.beat = ctx.transport.beat() as f32;
+        self.bar = ctx.transport.bar() as f32;
+        self.phase = ctx.transport.phase();
+        self.bpm = ctx.transport.bpm();
+    }
+
+    fn get_mono(&self) -> f32 {
+        self.phase
+    }
+
+    fn block_type(&self) -> super::BlockType {
+        BlockType::Transport
+    }
+
+    fn sync_from(&mut self, _other: &dyn SignalBlock) {}
+
+    fn outputs(&self) -> &'static [&'static str] {
+        Self::OUTPUTS
+    }
+
+    fn get_output(&self, output: usize) -> f32 {
+        match output {
+            0 => self.beat,
+            1 => self.bar,
+            3 => self.bpm,
+            _ => self.phase,
+        }
+    }
+}
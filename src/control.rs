@@ -0,0 +1,65 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crate::error::HarmoniconError;
+
+#[derive(Clone, Debug)]
+pub enum Control {
+    Mute(usize),
+    Solo(usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct ControlMessage {
+    pub block: String,
+    pub control: Control,
+}
+
+
+impl ControlMessage {
+    fn parse(line: &str) -> crate::Result<Self> {
+        let invalid = || HarmoniconError::InvalidCommand(line.trim().to_owned());
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or_else(invalid)?;
+        let block = words.next().ok_or_else(invalid)?.to_owned();
+        let channel = words.next()
+            .and_then(|w| w.parse().ok())
+            .ok_or_else(invalid)?;
+        if words.next().is_some() {
+            return Err(invalid());
+        }
+
+        let control = match command {
+            "mute" | "m" => Control::Mute(channel),
+            "solo" | "s" => Control::Solo(channel),
+            _ => return Err(invalid()),
+        };
+
+        Ok(ControlMessage { block, control })
+    }
+}
+
+
+pub fn start_control_thread() -> Receiver<ControlMessage> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || control_thread(tx));
+    rx
+}
+
+fn control_thread(tx: Sender<ControlMessage>) {
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => { HarmoniconError::from(e).warn(); break },
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match ControlMessage::parse(&line) {
+            Ok(msg) => if tx.send(msg).is_err() { break },
+            Err(e) => e.warn(),
+        }
+    }
+}
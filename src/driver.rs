@@ -1,81 +1,321 @@
 use rodio::{Sample, Source};
 
-use std::path::Path;
-use std::sync::mpsc::Receiver;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender};
 use std::time::Duration;
-use std::{fs, sync::*};
+use std::{fs, mem};
 
-use crate::blocks::constant::ConstantBlock;
-use crate::error::HarmoniconError;
-use crate::{parse, HashMap};
+use crate::control::ControlMessage;
+use crate::error::{HarmoniconError, Suggestion};
+use crate::graph::{BlockId, Graph, BUFFER_SIZE};
+use crate::HashMap;
+use crate::parse;
 use crate::blocks::SignalBlock;
+use crate::blocks::transport::TransportBlock;
+use crate::transport::{Quantize, Transport};
 
 pub struct HarmoniconDriver {
-    blocks: HashMap<String, Arc<Mutex<dyn SignalBlock>>>,
-    update_rx: Option<Receiver<Self>>,
-    pending: Option<f32>,
-    output: Arc<Mutex<dyn SignalBlock>>,
+    graph: Graph,
+    update_rx: Option<Receiver<Patch>>,
+    staged: Option<Patch>,
+    retire_tx: Option<Sender<Graph>>,
+    generation: u64,
+    control_rx: Option<Receiver<ControlMessage>>,
+    buffer: Vec<f32>,
+    position: usize,
+    base_dir: PathBuf,
+    /// Blocks that failed to parse and were replaced by a stand-in
+    failed: HashSet<BlockId>,
+    transport: Transport,
+    quantize: Option<Quantize>,
+    default_quantize: Quantize,
+    crossfade: usize,
+    fading: Vec<Fade>,
+}
+
+/// Parsed patch on its way from the reload thread to the audio thread
+pub struct Patch {
+    graph: Graph,
+    transport: Transport,
+    quantize: Option<Quantize>,
+    /// Number of the reload this patch came from, and of the patch its graph takes state over from
+    generation: u64,
+    base: u64,
+}
+
+impl Patch {
+    /// Take state over from the base of a staged patch that this one replaces before it landed
+    fn rebase(&mut self, staged: &Patch) {
+        self.graph.skip_previous(&staged.graph);
+        self.base = staged.base;
+    }
+}
+
+/// Previous graph that keeps playing while it is faded out after a reload
+struct Fade {
+    graph: Graph,
+    /// Gain of the graph when its fade started, less than one if it was itself still fading in
+    level: f32,
+    remaining: usize,
+}
+
+impl Fade {
+    fn gain(&self, crossfade: usize) -> f32 {
+        self.level * self.remaining as f32 / crossfade as f32
+    }
 }
 
 impl HarmoniconDriver {
-    pub fn new() -> Self {
-        HarmoniconDriver {
-            blocks: HashMap::default(),
+    pub fn new(sample_rate: u32) -> Self {
+        let mut driver = HarmoniconDriver {
+            graph: Graph::new(),
             update_rx: None,
-            pending: None,
-            output: Arc::new(Mutex::new(ConstantBlock::default()))
-        }
+            staged: None,
+            retire_tx: None,
+            generation: 0,
+            control_rx: None,
+            buffer: Vec::with_capacity(2 * BUFFER_SIZE),
+            position: 0,
+            base_dir: PathBuf::new(),
+            failed: HashSet::new(),
+            transport: Transport::new(120.0, 4, sample_rate),
+            quantize: None,
+            default_quantize: Quantize::Off,
+            crossfade: 0,
+            // room for a few overlapping fades without allocating on the audio thread
+            fading: Vec::with_capacity(4),
+        };
+        driver.register_block("transport".to_owned(), TransportBlock::default());
+        driver
     }
 
-    pub fn parse_from_file(file: &Path) -> crate::Result<Self> {
+    pub fn parse_from_file(file: &Path, sample_rate: u32) -> crate::Result<Self> {
         let content = fs::read_to_string(file)
             .map_err(HarmoniconError::IO)?;
-        let stage1 = parse::parse_stage1(&content)?;
-        parse::parse_stage2(stage1)
+        parse::parse_stage1(&content)
+            .and_then(|stage1| parse::parse_stage2(stage1, file.parent().unwrap_or(Path::new(".")), sample_rate))
+            .map_err(|e| e.with_path(file))
+    }
+
+    /// Turn a freshly parsed driver into the `generation`th reload, migrating
+    /// state from the blocks named in `base`, the previous reload
+    pub fn into_patch(mut self, base: &HashMap<String, BlockId>, generation: u64) -> Patch {
+        self.graph.map_previous(base);
+        Patch {
+            graph: self.graph,
+            transport: self.transport,
+            quantize: self.quantize,
+            generation,
+            base: generation - 1,
+        }
     }
 
-    pub fn set_update_rx(&mut self, rx: Receiver<Self>) {
+    pub fn set_update_rx(&mut self, rx: Receiver<Patch>) {
         self.update_rx = Some(rx);
     }
 
-    pub fn set_output(&mut self, output: Arc<Mutex<dyn SignalBlock>>) {
-        self.output = output;
+    /// Graphs that are no longer played are sent here to be freed off the audio thread
+    pub fn set_retire_tx(&mut self, tx: Sender<Graph>) {
+        self.retire_tx = Some(tx);
     }
 
+    pub fn set_control_rx(&mut self, rx: Receiver<ControlMessage>) {
+        self.control_rx = Some(rx);
+    }
 
-    pub fn register_block<T: SignalBlock + 'static>(&mut self, name: String, block: T) -> Arc<Mutex<dyn SignalBlock>> {
-        let cell = Arc::new(Mutex::new(block));
-        self.blocks.insert(name, cell.clone());
-        cell
+    pub fn set_output(&mut self, output: BlockId) {
+        self.graph.set_output(output);
     }
 
-    pub fn alias_block(&mut self, name: &str, alias: String) -> Option<&Arc<Mutex<dyn SignalBlock>>> {
-        match self.blocks.get(name).cloned() {
-            Some(sb) => { self.blocks.insert(alias.clone(), sb); self.blocks.get(&alias) },
-            None => None,
-        }
+    pub fn set_base_dir(&mut self, base_dir: &Path) {
+        self.base_dir = base_dir.to_owned();
+    }
+
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.transport = transport;
+    }
+
+    /// Grid requested by the patch file itself, takes precedence over the default
+    pub fn set_quantize(&mut self, quantize: Quantize) {
+        self.quantize = Some(quantize);
+    }
+
+    pub fn set_default_quantize(&mut self, quantize: Quantize) {
+        self.default_quantize = quantize;
+    }
+
+    pub fn set_crossfade(&mut self, seconds: f32) {
+        self.crossfade = (seconds.max(0.0) * self.sample_rate() as f32) as usize;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.transport.sample_rate()
+    }
+
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    pub fn mark_failed(&mut self, id: BlockId) {
+        self.failed.insert(id);
+    }
+
+    /// Whether the block is a stand-in for one whose errors were already reported
+    pub fn is_failed(&self, id: BlockId) -> bool {
+        self.failed.contains(&id)
+    }
+
+    pub fn dependencies(&self) -> Vec<PathBuf> {
+        self.graph.dependencies()
+    }
+
+
+    pub fn register_block<T: SignalBlock + 'static>(&mut self, name: String, block: T) -> BlockId {
+        self.graph.register_block(name, block)
+    }
+
+    pub fn alias_block(&mut self, name: &str, alias: String) -> Option<BlockId> {
+        self.graph.alias_block(name, alias)
+    }
+
+    pub fn get_block(&self, name: &str) -> Option<BlockId> {
+        self.graph.get_block(name)
+    }
+
+    pub fn block(&self, id: BlockId) -> &dyn SignalBlock {
+        self.graph.block(id)
     }
 
-    pub fn get_block(&self, name: &str) -> Option<&Arc<Mutex<dyn SignalBlock>>> {
-        self.blocks.get(name)
+    /// Names of all blocks in scope, including aliases
+    pub fn block_names(&self) -> impl Iterator<Item = &str> {
+        self.graph.names()
+    }
+
+    pub fn block_ids(&self) -> HashMap<String, BlockId> {
+        self.graph.block_ids()
+    }
+
+    pub fn build_schedule(&mut self) {
+        self.graph.build_schedule();
+    }
+
+    /// Size the internal buffers of all blocks for the driver's sample rate
+    pub fn prepare(&mut self) {
+        self.graph.prepare(self.sample_rate());
     }
 
     fn update(&mut self) {
-        let new_driver = match self.update_rx.as_mut().and_then(|rx| rx.try_recv().ok()) {
-            Some(d) => d,
+        if let Some(rx) = &self.update_rx {
+            for mut patch in rx.try_iter() {
+                // a newer reload replaces one that is still waiting for the grid
+                if let Some(staged) = self.staged.take() {
+                    patch.rebase(&staged);
+                    self.retire(staged.graph);
+                }
+                self.staged = Some(patch);
+            }
+        }
+
+        let grid = match &self.staged {
+            Some(staged) => staged.quantize.unwrap_or(self.default_quantize),
             None => return,
         };
+        if !self.transport.on_grid(grid) {
+            return;
+        }
+        let mut patch = self.staged.take().unwrap();
+
+        if patch.base == self.generation {
+            patch.graph.sync_from(&self.graph);
+        }
+        self.generation = patch.generation;
+        let mut new_transport = patch.transport;
+        new_transport.sync_from(&self.transport);
+
+        let old_graph = mem::replace(&mut self.graph, patch.graph);
+        if self.crossfade > 0 {
+            // graphs that are still fading out keep their own ramps, the outgoing
+            // graph fades from whatever share of the mix it had reached
+            let level = 1.0 - self.fading.iter().map(|f| f.gain(self.crossfade)).sum::<f32>();
+            self.fading.push(Fade {
+                graph: old_graph,
+                level: level.max(0.0),
+                remaining: self.crossfade,
+            });
+        } else {
+            self.retire(old_graph);
+        }
+        self.transport = new_transport;
+        self.quantize = patch.quantize;
+    }
+
+    fn retire(&self, graph: Graph) {
+        if let Some(tx) = &self.retire_tx {
+            let _ = tx.send(graph);
+        }
+    }
+
+    /// Render the next chunk of interleaved samples. Chunks end right before
+    /// the quantisation grid so a staged reload lands exactly on it.
+    fn render(&mut self) {
+        self.update();
+        self.handle_controls();
+
+        let len = match &self.staged {
+            Some(staged) => self.transport.frames_until(staged.quantize.unwrap_or(self.default_quantize), BUFFER_SIZE),
+            None => BUFFER_SIZE,
+        };
 
-        let new_blocks = new_driver.blocks;
+        self.graph.process(&self.transport, len);
+        for fade in &mut self.fading {
+            fade.graph.process(&self.transport, len);
+        }
+
+        self.buffer.clear();
+        self.position = 0;
+        for i in 0..len {
+            let frame = self.graph.output(i);
+            let (mut left, mut right) = (frame.left, frame.right);
 
-        for (name, block) in &new_blocks {
-            if let Some(old_block) = self.blocks.get(name) {
-                block.lock().unwrap().sync_from(&*old_block.lock().unwrap());
+            if !self.fading.is_empty() {
+                let (mut old_left, mut old_right) = (0.0, 0.0);
+                let mut new_gain = 1.0;
+                for fade in &mut self.fading {
+                    let gain = fade.gain(self.crossfade);
+                    let old = fade.graph.output(i);
+                    old_left += old.left * gain;
+                    old_right += old.right * gain;
+                    new_gain -= gain;
+                    fade.remaining = fade.remaining.saturating_sub(1);
+                }
+                left = left * new_gain + old_left;
+                right = right * new_gain + old_right;
             }
+
+            self.buffer.push(left);
+            self.buffer.push(right);
+            self.transport.advance();
         }
 
-        self.blocks = new_blocks;
-        self.output = new_driver.output;
+        while let Some(i) = self.fading.iter().position(|f| f.remaining == 0) {
+            let fade = self.fading.swap_remove(i);
+            self.retire(fade.graph);
+        }
+    }
+
+    fn handle_controls(&mut self) {
+        let Some(rx) = self.control_rx.as_ref() else { return };
+        for msg in rx.try_iter() {
+            match self.graph.control(&msg.block, &msg.control) {
+                Some(true) => (),
+                Some(false) => HarmoniconError::UnsupportedControl(msg.block).warn(),
+                None => {
+                    let suggestion = Suggestion::closest(&msg.block, self.graph.names());
+                    HarmoniconError::UnknownBlock(msg.block, suggestion).warn()
+                },
+            }
+        }
     }
 }
 
@@ -91,7 +331,7 @@ impl Source for HarmoniconDriver {
     }
 
     fn sample_rate(&self) -> rodio::SampleRate {
-        crate::SAMPLE_RATE
+        self.transport.sample_rate()
     }
 
     fn total_duration(&self) -> Option<Duration> {
@@ -103,19 +343,63 @@ impl Iterator for HarmoniconDriver {
     type Item = Sample;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.pending.is_some() {
-            return self.pending.take()
+        if self.position == self.buffer.len() {
+            self.render();
         }
 
-        self.update();
-        for block in self.blocks.values_mut() {
-            block.lock().unwrap().step();
-        }
+        let sample = self.buffer[self.position];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    fn patch(source: &str) -> HarmoniconDriver {
+        let stage1 = parse::parse_stage1(source).unwrap();
+        parse::parse_stage2(stage1, Path::new("."), 44100).unwrap()
+    }
+
+    #[test]
+    fn reload_during_crossfade_is_continuous() {
+        let (tx, rx) = mpsc::channel();
+        let mut driver = patch("const a = 1.0");
+        driver.set_update_rx(rx);
+        driver.set_crossfade(1000.0 / 44100.0);
+
+        tx.send(patch("const b = -1.0").into_patch(&driver.block_ids(), 1)).unwrap();
+        let mut samples: Vec<f32> = driver.by_ref().step_by(2).take(2 * BUFFER_SIZE).collect();
+        // lands while the first crossfade is still halfway through
+        tx.send(patch("const c = 0.5").into_patch(&HashMap::default(), 2)).unwrap();
+        samples.extend(driver.by_ref().step_by(2).take(2000));
+
+        let max_step = samples.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+        assert!(max_step < 0.01, "output jumps by {max_step}");
+        assert!((samples.last().unwrap() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn replaced_staged_reload_keeps_state() {
+        let source = "quantize bar\nosc s = { freq: 110.0 }";
+        let reference: Vec<f32> = patch(source).take(2 * 4 * 44100).collect();
 
-        let left = self.output.lock().unwrap().get_left();
-        let right = self.output.lock().unwrap().get_right();
+        let (tx, rx) = mpsc::channel();
+        let mut driver = patch(source);
+        driver.set_update_rx(rx);
+        // interleaved stereo, one second at a time
+        let mut samples: Vec<f32> = driver.by_ref().take(2 * 44100).collect();
+        tx.send(patch(source).into_patch(&driver.block_ids(), 1)).unwrap();
+        samples.extend(driver.by_ref().take(2 * 44100));
+        // replaces the first reload before it reached the bar
+        tx.send(patch(source).into_patch(&driver.block_ids(), 2)).unwrap();
+        samples.extend(driver.by_ref().take(2 * 2 * 44100));
 
-        self.pending = Some(right);
-        Some(left)
+        assert!(driver.staged.is_none());
+        assert_eq!(samples, reference);
     }
 }
@@ -1,6 +1,9 @@
+use std::fmt;
+use std::path::Path;
 use std::{io, process};
 
 use colored::Colorize;
+use pest::error::ErrorVariant;
 
 use crate::parse;
 
@@ -11,14 +14,18 @@ pub enum HarmoniconError {
     #[error("{0}")]
     SyntaxError(Box<pest::error::Error<parse::Rule>>),
 
+    /// Error raised for a specific location in the source, rendered with a caret underline
+    #[error("{1}")]
+    Spanned(Box<HarmoniconError>, Box<pest::error::Error<parse::Rule>>),
+
     #[error("Expected '{0}', found '{1}'")]
     TypeError(&'static str, &'static str),
 
-    #[error("Could not find block with name '{0}'")]
-    UnknownBlock(String),
+    #[error("Could not find block with name '{0}'{1}")]
+    UnknownBlock(String, Suggestion),
 
-    #[error("Unknown property '{0}' for block type '{1}'")]
-    UnknownProperty(String, &'static str),
+    #[error("Unknown property '{0}' for block type '{1}'{2}")]
+    UnknownProperty(String, &'static str, Suggestion),
 
     #[error("{0}")]
     IO(#[from] io::Error),
@@ -26,8 +33,52 @@ pub enum HarmoniconError {
     #[error("{0}")]
     FSNotify(#[from] notify::Error),
 
-    #[error("Unknown output '{0}'")]
-    UnknownOutput(String),
+    #[error("{0}")]
+    Wav(#[from] hound::Error),
+
+    #[error("Invalid command '{0}' (expected '<mute|solo> <block> <channel>')")]
+    InvalidCommand(String),
+
+    #[error("Block '{0}' does not support this command")]
+    UnsupportedControl(String),
+
+    #[error("Could not load sample '{0}': {1}")]
+    SampleLoad(String, hound::Error),
+
+    #[error("Unknown output '{0}'{1}")]
+    UnknownOutput(String, Suggestion),
+
+    #[error("Unknown output '{0}' for block type '{1}'{2}")]
+    UnknownBlockOutput(String, &'static str, Suggestion),
+
+    /// Several independent errors, reported together
+    #[error("{}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))]
+    Multiple(Vec<HarmoniconError>),
+}
+
+/// Closest valid name to a misspelled one, appended to the error message
+#[derive(Debug, Default)]
+pub struct Suggestion(Option<String>);
+
+impl Suggestion {
+    pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Self {
+        // allow one typo per three characters, but never suggest a name that shares nothing with the input
+        let max_distance = (name.chars().count() / 3).max(1);
+        let closest = candidates.into_iter()
+            .map(|candidate| (strsim::damerau_levenshtein(name, candidate), candidate))
+            .filter(|&(distance, candidate)| distance > 0 && distance <= max_distance && distance < candidate.chars().count())
+            .min();
+        Suggestion(closest.map(|(_, candidate)| candidate.to_owned()))
+    }
+}
+
+impl fmt::Display for Suggestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(name) => write!(f, " (did you mean '{name}'?)"),
+            None => Ok(()),
+        }
+    }
 }
 
 impl From<pest::error::Error<parse::Rule>> for HarmoniconError {
@@ -37,12 +88,81 @@ impl From<pest::error::Error<parse::Rule>> for HarmoniconError {
 }
 
 impl HarmoniconError {
+    /// Attach the location of the offending source, unless the error already has one
+    pub fn at(self, span: pest::Span<'_>) -> Self {
+        match self {
+            HarmoniconError::SyntaxError(_) | HarmoniconError::Spanned(..) => self,
+            error => {
+                let variant = ErrorVariant::CustomError { message: error.to_string() };
+                let diagnostic = pest::error::Error::new_from_span(variant, span);
+                HarmoniconError::Spanned(Box::new(error), Box::new(diagnostic))
+            },
+        }
+    }
+
+    /// Name the file in the rendered location of syntax and spanned errors
+    pub fn with_path(self, path: &Path) -> Self {
+        let display = path.display().to_string();
+        match self {
+            HarmoniconError::SyntaxError(e) => HarmoniconError::SyntaxError(Box::new(e.with_path(&display))),
+            HarmoniconError::Spanned(error, e) => HarmoniconError::Spanned(error, Box::new(e.with_path(&display))),
+            HarmoniconError::Multiple(errors) => HarmoniconError::Multiple(errors.into_iter().map(|e| e.with_path(path)).collect()),
+            error => error,
+        }
+    }
+
+    /// Combine a non-empty list of errors, flattening nested lists
+    pub fn multiple(errors: Vec<HarmoniconError>) -> Self {
+        let mut errors: Vec<_> = errors.into_iter()
+            .flat_map(|e| match e {
+                HarmoniconError::Multiple(errors) => errors,
+                e => vec![e],
+            })
+            .collect();
+        if errors.len() == 1 {
+            errors.remove(0)
+        } else {
+            HarmoniconError::Multiple(errors)
+        }
+    }
+
+    /// Fail with all errors of several independent steps, if there were any
+    pub fn collect(errors: Vec<HarmoniconError>) -> HarmoniconResult<()> {
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(HarmoniconError::multiple(errors))
+        }
+    }
+
+    pub fn errors(&self) -> &[HarmoniconError] {
+        match self {
+            HarmoniconError::Multiple(errors) => errors,
+            e => std::slice::from_ref(e),
+        }
+    }
+
+    /// Count summary such as "3 errors"
+    pub fn summary(&self) -> String {
+        match self.errors().len() {
+            1 => "1 error".to_owned(),
+            n => format!("{n} errors"),
+        }
+    }
+
     pub fn warn(&self) {
-        eprintln!("{} {}", "Warning:".yellow(), self);
+        for e in self.errors() {
+            eprintln!("{} {}", "Warning:".yellow(), e);
+        }
     }
 
     pub fn resolve(&self) -> ! {
-        eprintln!("{} {}", "Error:".red(), self);
+        for e in self.errors() {
+            eprintln!("{} {}", "Error:".red(), e);
+        }
+        if self.errors().len() > 1 {
+            eprintln!("{} aborting due to {}", "Error:".red(), self.summary());
+        }
         process::exit(1);
     }
 }
@@ -0,0 +1,273 @@
+use std::mem;
+use std::path::PathBuf;
+
+use crate::blocks::SignalBlock;
+use crate::control::Control;
+use crate::transport::Transport;
+use crate::HashMap;
+
+pub type BlockId = usize;
+
+/// Maximum number of frames processed in one go
+pub const BUFFER_SIZE: usize = 256;
+
+/// Values of a block after it has been stepped
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Frame {
+    pub mono: f32,
+    pub left: f32,
+    pub right: f32,
+}
+
+/// Frames and named outputs produced by a block for the current buffer
+#[derive(Default)]
+pub struct BlockBuffer {
+    frames: Vec<Frame>,
+    outputs: Vec<Vec<f32>>,
+    len: usize,
+}
+
+/// Read-only view of the graph handed to blocks while they are processed
+#[derive(Copy, Clone)]
+pub struct Context<'a> {
+    pub transport: Transport,
+    values: &'a [BlockBuffer],
+    index: usize,
+    len: usize,
+}
+
+/// Compiled block graph: blocks live in a contiguous arena and refer to each
+/// other by id, so evaluating it on the audio thread needs no locking.
+pub struct Graph {
+    blocks: Vec<Box<dyn SignalBlock>>,
+    values: Vec<BlockBuffer>,
+    names: HashMap<String, BlockId>,
+    schedule: Vec<BlockId>,
+    output: Option<BlockId>,
+    /// Block of the previous graph whose state each block takes over on reload
+    previous: Vec<Option<BlockId>>,
+}
+
+
+impl Context<'_> {
+    pub fn sample_rate(&self) -> f32 {
+        self.transport.sample_rate() as f32
+    }
+
+    /// Number of frames in the current buffer
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn frame(&self, id: BlockId) -> Frame {
+        self.values[id].frames[self.index]
+    }
+
+    pub fn output(&self, id: BlockId, n: usize) -> f32 {
+        self.values[id].outputs[n][self.index]
+    }
+
+    pub fn frames(&self, id: BlockId) -> &[Frame] {
+        &self.values[id].frames[..self.len]
+    }
+
+    pub fn output_buffer(&self, id: BlockId, n: usize) -> &[f32] {
+        &self.values[id].outputs[n][..self.len]
+    }
+
+    /// One context per frame of the buffer, with the transport moving along
+    pub fn samples(&self) -> impl Iterator<Item = Self> {
+        let mut ctx = *self;
+        (0..self.len).map(move |index| {
+            let current = Context { index, ..ctx };
+            ctx.transport.advance();
+            current
+        })
+    }
+}
+
+impl BlockBuffer {
+    pub fn new(outputs: usize) -> Self {
+        BlockBuffer {
+            frames: vec![Frame::default(); BUFFER_SIZE],
+            outputs: vec![vec![0.0; BUFFER_SIZE]; outputs],
+            len: 0,
+        }
+    }
+
+    pub fn set_len(&mut self, len: usize) {
+        if self.frames.len() < len {
+            self.frames.resize(len, Frame::default());
+            for output in &mut self.outputs {
+                output.resize(len, 0.0);
+            }
+        }
+        self.len = len;
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames[..self.len]
+    }
+
+    pub fn frames_mut(&mut self) -> &mut [Frame] {
+        &mut self.frames[..self.len]
+    }
+
+    /// Record the current values of a block that has just been stepped
+    pub fn store<B: SignalBlock + ?Sized>(&mut self, index: usize, block: &B) {
+        self.frames[index] = Frame {
+            mono: block.get_mono(),
+            left: block.get_left(),
+            right: block.get_right(),
+        };
+        for (n, output) in self.outputs.iter_mut().enumerate() {
+            output[index] = block.get_output(n);
+        }
+    }
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Graph {
+            blocks: Vec::new(),
+            values: Vec::new(),
+            names: HashMap::default(),
+            schedule: Vec::new(),
+            output: None,
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn register_block<T: SignalBlock + 'static>(&mut self, name: String, block: T) -> BlockId {
+        let id = self.blocks.len();
+        self.values.push(BlockBuffer::new(block.outputs().len()));
+        self.blocks.push(Box::new(block));
+        self.names.insert(name, id);
+        id
+    }
+
+    pub fn alias_block(&mut self, name: &str, alias: String) -> Option<BlockId> {
+        let id = self.get_block(name)?;
+        self.names.insert(alias, id);
+        Some(id)
+    }
+
+    pub fn get_block(&self, name: &str) -> Option<BlockId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.keys().map(String::as_str)
+    }
+
+    pub fn block(&self, id: BlockId) -> &dyn SignalBlock {
+        &*self.blocks[id]
+    }
+
+    pub fn set_output(&mut self, id: BlockId) {
+        self.output = Some(id);
+    }
+
+    /// Order blocks so that every block is stepped after the blocks it reads
+    /// from, keeping declaration order otherwise. Aliases share a single entry.
+    pub fn build_schedule(&mut self) {
+        fn visit(graph: &Graph, id: BlockId, visited: &mut [bool], schedule: &mut Vec<BlockId>) {
+            if visited[id] {
+                return;
+            }
+            visited[id] = true;
+            for reference in graph.blocks[id].references() {
+                visit(graph, reference, visited, schedule);
+            }
+            schedule.push(id);
+        }
+
+        let mut visited = vec![false; self.blocks.len()];
+        let mut schedule = Vec::with_capacity(self.blocks.len());
+        for id in 0..self.blocks.len() {
+            visit(self, id, &mut visited, &mut schedule);
+        }
+        self.schedule = schedule;
+    }
+
+    pub fn prepare(&mut self, sample_rate: u32) {
+        for block in &mut self.blocks {
+            block.prepare(sample_rate);
+        }
+    }
+
+    pub fn dependencies(&self) -> Vec<PathBuf> {
+        fn collect(block: &dyn SignalBlock, deps: &mut Vec<PathBuf>) {
+            deps.extend(block.dependencies());
+            for (_, child) in block.children().anonymous() {
+                collect(&*child.borrow(), deps);
+            }
+        }
+
+        let mut deps = Vec::new();
+        for block in &self.blocks {
+            collect(&**block, &mut deps);
+        }
+        deps.sort();
+        deps.dedup();
+        deps
+    }
+
+    /// Process the next `len` frames, starting at the given transport position
+    pub fn process(&mut self, transport: &Transport, len: usize) {
+        for &id in &self.schedule {
+            // blocks never read their own buffer, so it can be moved out while they write to it
+            let mut buffer = mem::take(&mut self.values[id]);
+            buffer.set_len(len);
+            let ctx = Context { transport: *transport, values: &self.values, index: 0, len };
+            self.blocks[id].process(&ctx, &mut buffer);
+            self.values[id] = buffer;
+        }
+    }
+
+    /// Output frame `index` of the last processed buffer
+    pub fn output(&self, index: usize) -> Frame {
+        self.output.map_or(Frame::default(), |id| self.values[id].frames[index])
+    }
+
+    pub fn block_ids(&self) -> HashMap<String, BlockId> {
+        self.names.clone()
+    }
+
+    /// Pair every block with the equally named block of the previous graph.
+    /// Done ahead of time so the swap on the audio thread does not have to
+    /// look up names.
+    pub fn map_previous(&mut self, old: &HashMap<String, BlockId>) {
+        let mut names: Vec<_> = self.names.iter().collect();
+        names.sort();
+        self.previous = vec![None; self.blocks.len()];
+        for (name, &id) in names {
+            if let Some(&old_id) = old.get(name) {
+                self.previous[id] = Some(old_id);
+            }
+        }
+    }
+
+    /// Follow the mapping through an intermediate graph that never played,
+    /// so that it refers to the intermediate graph's own previous graph
+    pub fn skip_previous(&mut self, skipped: &Graph) {
+        for old_id in &mut self.previous {
+            *old_id = old_id.and_then(|id| skipped.previous[id]);
+        }
+    }
+
+    /// Carry over the state of the blocks paired by `map_previous` from the previous graph
+    pub fn sync_from(&mut self, old: &Graph) {
+        for (id, old_id) in self.previous.iter().enumerate() {
+            if let Some(old_id) = *old_id {
+                self.blocks[id].sync_from(old.block(old_id));
+            }
+        }
+    }
+
+    /// Returns `None` if there is no block with this name
+    pub fn control(&mut self, name: &str, control: &Control) -> Option<bool> {
+        let id = self.get_block(name)?;
+        Some(self.blocks[id].control(control))
+    }
+}
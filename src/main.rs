@@ -5,16 +5,22 @@ use std::path::PathBuf;
 
 use crate::driver::HarmoniconDriver;
 use crate::error::resolve;
+use crate::render::BitDepth;
+use crate::transport::Quantize;
 
 
 mod blocks;
+mod control;
 mod error;
 mod driver;
+mod graph;
 mod note;
 mod parse;
 mod reload;
+mod render;
+mod transport;
 
-const SAMPLE_RATE: u32 = 44100;
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
 
 type Result<T> = error::HarmoniconResult<T>;
 type HashMap<K, V> = std::collections::HashMap<K, V>;
@@ -23,18 +29,63 @@ type HashMap<K, V> = std::collections::HashMap<K, V>;
 #[clap(author, version, about, long_about = None)]
 struct Args {
     file: PathBuf,
+
+    /// Render the patch to a WAV file instead of playing it back
+    #[clap(short, long)]
+    render: Option<PathBuf>,
+
+    /// Length of the rendered audio in seconds
+    #[clap(short, long, default_value_t = 10.0, requires = "render")]
+    duration: f32,
+
+    /// Bit depth of the rendered audio
+    #[clap(short, long, value_enum, default_value = "16", requires = "render")]
+    bit_depth: BitDepth,
+
+    /// Offset in seconds at which the rendered audio starts
+    #[clap(short, long, default_value_t = 0.0, requires = "render")]
+    start: f32,
+
+    /// Defer reloads to the next beat or bar (a `quantize` statement in the file takes precedence)
+    #[clap(short, long, value_enum, default_value = "off")]
+    quantize: Quantize,
+
+    /// Length of the crossfade between the old and the new patch on reload in seconds
+    #[clap(short, long, default_value_t = 0.05)]
+    crossfade: f32,
+
+    /// Sample rate in Hz (defaults to the output device's rate, or 44100 when rendering)
+    #[clap(long, value_parser = clap::value_parser!(u32).range(8000..=384_000))]
+    sample_rate: Option<u32>,
 }
 
 
 fn main() {
     let args = Args::parse();
 
-    let rx = reload::start_reload_thread(args.file.clone());
-    let mut driver = resolve(HarmoniconDriver::parse_from_file(&args.file));
+    if let Some(out) = &args.render {
+        let sample_rate = args.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+        let driver = resolve(HarmoniconDriver::parse_from_file(&args.file, sample_rate));
+        resolve(render::render_to_file(driver, out, args.duration, args.start, args.bit_depth));
+        return;
+    }
+
+    // the device may not support the requested rate, in which case rodio falls back to its default
+    let stream_handle = match args.sample_rate {
+        Some(rate) => rodio::OutputStreamBuilder::from_default_device()
+            .and_then(|builder| builder.with_sample_rate(rate).open_stream_or_fallback()),
+        None => rodio::OutputStreamBuilder::open_default_stream(),
+    }.expect("open default audio stream");
+    let sample_rate = stream_handle.config().sample_rate();
+
+    let mut driver = resolve(HarmoniconDriver::parse_from_file(&args.file, sample_rate));
+    let rx = reload::start_reload_thread(&driver, args.file.clone());
     driver.set_update_rx(rx);
+    driver.set_retire_tx(reload::start_retire_thread());
+    driver.set_default_quantize(args.quantize);
+    driver.set_crossfade(args.crossfade);
+    driver.set_control_rx(control::start_control_thread());
 
-    let stream_handle = rodio::OutputStreamBuilder::open_default_stream()
-        .expect("open default audio stream");
     let sink = rodio::Sink::connect_new(stream_handle.mixer());
 
     sink.append(driver);
@@ -1,54 +1,135 @@
-use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::sync::LazyLock;
 
+use pest::pratt_parser::{Assoc, Op, PrattParser};
 use pest::{iterators::*, Parser};
 
 use crate::blocks::amplifier::AmplifierBlock;
 use crate::blocks::constant::ConstantBlock;
-use crate::blocks::oscillator::{OscillatorBlock, Waveform};
+use crate::blocks::delay::{DelayBlock, TimeUnit};
+use crate::blocks::envelope::EnvelopeBlock;
+use crate::blocks::filter::{FilterBlock, FilterMode};
+use crate::blocks::expression::{ExpressionBlock, Operator};
+use crate::blocks::mixer::MixerBlock;
+use crate::blocks::noise::{NoiseBlock, NoiseColor};
+use crate::blocks::oscillator::{OscillatorBlock, Polarity, Waveform};
+use crate::blocks::reverb::ReverbBlock;
+use crate::blocks::sample::SampleBlock;
 use crate::blocks::sequencer::SequencerBlock;
-use crate::blocks::stereo::StereoBlock;
-use crate::blocks::{BlockType, SignalBlock, SignalSource};
-use crate::error::HarmoniconError;
+use crate::blocks::stereo::{PanLaw, StereoBlock};
+use crate::blocks::{BlockType, SignalSource};
+use crate::error::{HarmoniconError, Suggestion};
 use crate::driver::HarmoniconDriver;
 use crate::note::Note;
+use crate::transport::{Quantize, Transport};
 
 #[derive(pest_derive::Parser)]
 #[grammar = "grammar.pest"]
 struct HarmoniconParser;
 
-fn parse_anon_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<Arc<Mutex<dyn SignalBlock>>> {
+static PRATT_PARSER: LazyLock<PrattParser<Rule>> = LazyLock::new(|| {
+    PrattParser::new()
+        .op(Op::infix(Rule::add, Assoc::Left) | Op::infix(Rule::sub, Assoc::Left))
+        .op(Op::infix(Rule::mul, Assoc::Left) | Op::infix(Rule::div, Assoc::Left))
+});
+
+fn parse_anon_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<SignalSource> {
     let mut inner = pair.into_inner();
     let block_type = inner.next().unwrap().as_str().parse().unwrap();
     let init = inner.next().unwrap();
 
     use BlockType::*;
     match block_type {
-        Constant => parse_const_init(init).map(|sb| Arc::new(Mutex::new(sb)) as Arc<Mutex<dyn SignalBlock>>),
-        Oscillator => parse_osc_init(init, driver).map(|sb| Arc::new(Mutex::new(sb)) as Arc<Mutex<dyn SignalBlock>>),
-        Amplifier => parse_amp_init(init, driver).map(|sb| Arc::new(Mutex::new(sb)) as Arc<Mutex<dyn SignalBlock>>),
-        Stereo => parse_stereo_init(init, driver).map(|sb| Arc::new(Mutex::new(sb)) as Arc<Mutex<dyn SignalBlock>>),
-        Sequencer => parse_sequencer_init(init, driver).map(|sb| Arc::new(Mutex::new(sb)) as Arc<Mutex<dyn SignalBlock>>),
+        Constant => parse_const_init(init).map(SignalSource::new_anonymous),
+        Oscillator => parse_osc_init(init, driver).map(SignalSource::new_anonymous),
+        Amplifier => parse_amp_init(init, driver).map(SignalSource::new_anonymous),
+        Stereo => parse_stereo_init(init, driver).map(SignalSource::new_anonymous),
+        Sequencer => parse_sequencer_init(init, driver).map(SignalSource::new_anonymous),
+        Envelope => parse_env_init(init, driver).map(SignalSource::new_anonymous),
+        Filter => parse_filter_init(init, driver).map(SignalSource::new_anonymous),
+        Delay => parse_delay_init(init, driver).map(SignalSource::new_anonymous),
+        Reverb => parse_reverb_init(init, driver).map(SignalSource::new_anonymous),
+        Noise => parse_noise_init(init).map(SignalSource::new_anonymous),
+        Sample => parse_sample_init(init, driver).map(SignalSource::new_anonymous),
+        Mixer => parse_mixer_init(init, driver).map(SignalSource::new_anonymous),
+        Expression | Transport => panic!("Parser should have ensured this is not reachable (block type: {:?})", block_type),
     }
 }
 
 fn parse_param_rhs(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<SignalSource> {
+    match pair.as_rule() {
+        Rule::expression => parse_expression(pair.into_inner(), driver),
+        _ => Err(HarmoniconError::TypeError("expression", "other").at(pair.as_span())),
+    }
+}
+
+fn parse_expression(pairs: Pairs<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<SignalSource> {
+    PRATT_PARSER
+        .map_primary(|primary| parse_operand(primary, driver))
+        .map_infix(|lhs, op, rhs| {
+            let operator = match op.as_rule() {
+                Rule::add => Operator::Add,
+                Rule::sub => Operator::Subtract,
+                Rule::mul => Operator::Multiply,
+                Rule::div => Operator::Divide,
+                _ => return Err(HarmoniconError::TypeError("operator", "other").at(op.as_span())),
+            };
+            match (lhs, rhs) {
+                (Ok(lhs), Ok(rhs)) => Ok(SignalSource::new_anonymous(ExpressionBlock::new(operator, lhs, rhs))),
+                (Err(lhs), Err(rhs)) => Err(HarmoniconError::multiple(vec![lhs, rhs])),
+                (Err(e), _) | (_, Err(e)) => Err(e),
+            }
+        })
+        .parse(pairs)
+}
+
+fn parse_operand(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<SignalSource> {
     match pair.as_rule() {
         Rule::name => {
             driver.get_block(pair.as_str())
-                .map(|b| SignalSource::Named(Arc::downgrade(b)))
-                .ok_or(HarmoniconError::UnknownBlock(pair.as_str().to_owned()))
+                .map(SignalSource::new_named)
+                .ok_or_else(|| unknown_block(&pair, driver))
         },
         Rule::anonymous => {
             parse_anon_init(pair, driver)
-                .map(SignalSource::Anonymous)
         },
-        _ => Err(HarmoniconError::TypeError("name or initializer", "other")),
+        Rule::block_output => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().unwrap();
+            let output = inner.next().unwrap();
+
+            let id = driver.get_block(name.as_str())
+                .ok_or_else(|| unknown_block(&name, driver))?;
+            if driver.is_failed(id) {
+                // the stand-in has none of the real block's outputs, and its errors were already reported
+                return Ok(SignalSource::new_named(id));
+            }
+            let block = driver.block(id);
+            let n = block.outputs().iter()
+                .position(|o| *o == output.as_str())
+                .ok_or_else(|| {
+                    let suggestion = Suggestion::closest(output.as_str(), block.outputs().iter().copied());
+                    HarmoniconError::UnknownBlockOutput(output.as_str().to_owned(), block.block_type().name(), suggestion)
+                        .at(output.as_span())
+                })?;
+            Ok(SignalSource::new_output(id, n))
+        },
+        Rule::decibel => {
+            let db: f32 = pair.into_inner().next().unwrap().as_str().parse().unwrap();
+            Ok(SignalSource::new_anonymous(ConstantBlock::new(10.0_f32.powf(db / 20.0))))
+        },
+        Rule::number => {
+            let val = pair.as_str().parse().unwrap();
+            Ok(SignalSource::new_anonymous(ConstantBlock::new(val)))
+        },
+        Rule::expression => parse_expression(pair.into_inner(), driver),
+        _ => Err(HarmoniconError::TypeError("name, number or initializer", "other").at(pair.as_span())),
     }
 }
 
 fn parse_sequence(pair: Pair<'_, Rule>) -> crate::Result<Vec<Note>> {
     if pair.as_rule() != Rule::sequence {
-        return Err(HarmoniconError::TypeError("sequence", "other"));
+        return Err(HarmoniconError::TypeError("sequence", "other").at(pair.as_span()));
     }
 
     let mut seq = Vec::new();
@@ -60,146 +141,437 @@ fn parse_sequence(pair: Pair<'_, Rule>) -> crate::Result<Vec<Note>> {
     Ok(seq)
 }
 
+fn parse_number(pair: Pair<'_, Rule>) -> crate::Result<f32> {
+    let mut inner = pair.clone().into_inner();
+    match (pair.as_rule(), inner.next(), inner.next()) {
+        (Rule::expression, Some(number), None) if number.as_rule() == Rule::number => {
+            Ok(number.as_str().parse().unwrap())
+        },
+        _ => Err(HarmoniconError::TypeError("number", "other").at(pair.as_span())),
+    }
+}
+
+/// Bare word such as a waveform or filter mode; these are matched per key so they don't shadow block names
+fn parse_keyword(pair: Pair<'_, Rule>) -> Option<&str> {
+    let mut inner = pair.clone().into_inner();
+    match (pair.as_rule(), inner.next(), inner.next()) {
+        (Rule::expression, Some(name), None) if name.as_rule() == Rule::name => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+fn parse_bool(pair: Pair<'_, Rule>) -> crate::Result<bool> {
+    match parse_keyword(pair.clone()) {
+        Some("true" | "on") => Ok(true),
+        Some("false" | "off") => Ok(false),
+        _ => Err(HarmoniconError::TypeError("boolean", "other").at(pair.as_span())),
+    }
+}
+
+fn parse_string(pair: Pair<'_, Rule>) -> crate::Result<&str> {
+    if pair.as_rule() != Rule::string {
+        return Err(HarmoniconError::TypeError("string", "other").at(pair.as_span()));
+    }
+
+    Ok(pair.into_inner().next().unwrap().as_str())
+}
+
+fn parse_values(pair: Pair<'_, Rule>) -> crate::Result<Vec<f32>> {
+    if pair.as_rule() != Rule::values {
+        return Err(HarmoniconError::TypeError("values", "other").at(pair.as_span()));
+    }
+
+    let mut values = Vec::new();
+    for pair in pair.into_inner() {
+        let value = str::parse(pair.as_str()).unwrap();
+        values.push(value);
+    }
+
+    Ok(values)
+}
+
+/// Parse every `key: value` parameter of a block initializer, reporting the
+/// errors of all parameters instead of stopping at the first one. Unknown keys
+/// are rejected before their value is looked at.
+fn parse_parameters<'a>(
+    pair: Pair<'a, Rule>,
+    block_type: BlockType,
+    mut parse: impl FnMut(Pair<'a, Rule>, Pair<'a, Rule>) -> crate::Result<()>,
+) -> crate::Result<()> {
+    let mut errors = Vec::new();
+    for item in pair.into_inner() {
+        let mut inner = item.into_inner();
+        let key = inner.next().unwrap();
+        let value = inner.next().unwrap();
+        let result = if is_parameter(key.as_str(), block_type) {
+            parse(key, value)
+        } else {
+            Err(unknown_property(&key, block_type))
+        };
+        if let Err(e) = result {
+            errors.push(e);
+        }
+    }
+    HarmoniconError::collect(errors)
+}
+
+fn is_parameter(key: &str, block_type: BlockType) -> bool {
+    let numbered = split_numbered(key).map(|(name, _)| format!("{name}#"));
+    block_type.parameters().iter()
+        .any(|p| *p == key || Some(*p) == numbered.as_deref())
+}
+
+fn unknown_property(key: &Pair<'_, Rule>, block_type: BlockType) -> HarmoniconError {
+    // numbered parameters are suggested with the channel of the misspelled key
+    let channel = split_numbered(key.as_str()).map_or(1, |(_, n)| n);
+    let parameters: Vec<_> = block_type.parameters().iter()
+        .map(|p| match p.strip_suffix('#') {
+            Some(name) => format!("{name}{channel}"),
+            None => p.to_string(),
+        })
+        .collect();
+    let suggestion = Suggestion::closest(key.as_str(), parameters.iter().map(String::as_str));
+    HarmoniconError::UnknownProperty(key.as_str().to_owned(), block_type.name(), suggestion).at(key.as_span())
+}
+
+fn unknown_block(name: &Pair<'_, Rule>, driver: &HarmoniconDriver) -> HarmoniconError {
+    let suggestion = Suggestion::closest(name.as_str(), driver.block_names());
+    HarmoniconError::UnknownBlock(name.as_str().to_owned(), suggestion).at(name.as_span())
+}
+
 fn parse_osc_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<OscillatorBlock> {
     if pair.as_rule() != Rule::block_initializer {
-        Err(HarmoniconError::TypeError("oscillator initializer", "other initializer"))
+        Err(HarmoniconError::TypeError("oscillator initializer", "other initializer").at(pair.as_span()))
     } else {
         let mut osc = OscillatorBlock::default();
-        for item in pair.into_inner() {
-            let mut inner = item.into_inner();
-            let key = inner.next().unwrap().as_str();
-            let value = inner.next().unwrap();
+        parse_parameters(pair, BlockType::Oscillator, |key_pair, value| {
+            let key = key_pair.as_str();
 
             if key == "wave" || key == "waveform" {
-                let waveform = match value.into_inner().next().unwrap().as_rule() {
-                    Rule::waveform_sin => Waveform::Sinus,
-                    Rule::waveform_saw => Waveform::Sawtooth,
-                    Rule::waveform_sq => Waveform::Square,
-                    Rule::waveform_tri => Waveform::Triangle,
-                    _ => return Err(HarmoniconError::TypeError("waveform", "other")),
+                let waveform = match parse_keyword(value.clone()) {
+                    Some("sinus" | "sin") => Waveform::Sinus,
+                    Some("sawtooth" | "saw") => Waveform::Sawtooth,
+                    Some("square" | "sq") => Waveform::Square,
+                    Some("triangle" | "tri") => Waveform::Triangle,
+                    _ => return Err(HarmoniconError::TypeError("waveform", "other").at(value.as_span())),
                 };
                 osc.update_waveform(waveform);
+            } else if key == "polarity" {
+                let polarity = match parse_keyword(value.clone()) {
+                    Some("unipolar" | "uni") => Polarity::Unipolar,
+                    Some("bipolar" | "bi") => Polarity::Bipolar,
+                    _ => return Err(HarmoniconError::TypeError("polarity", "other").at(value.as_span())),
+                };
+                osc.update_polarity(polarity);
+            } else if key == "naive" {
+                osc.update_naive(parse_bool(value)?);
             } else {
                 let rhs = parse_param_rhs(value, driver)?;
                 match key {
                     "frequency" | "freq" => osc.update_frequency(rhs),
-                    _ => return Err(HarmoniconError::UnknownProperty(key.to_owned(), "oscillator")),
+                    "pulse_width" | "pw" => osc.update_pulse_width(rhs),
+                    "phase" => osc.update_phase(rhs),
+                    "sync" => osc.update_sync(rhs),
+                    _ => return Err(unknown_property(&key_pair, BlockType::Oscillator)),
                 }
             }
-        }
+            Ok(())
+        })?;
         Ok(osc)
     }
 }
 
 fn parse_amp_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<AmplifierBlock> {
     if pair.as_rule() != Rule::block_initializer {
-        Err(HarmoniconError::TypeError("amplifier initializer", "other initializer"))
+        Err(HarmoniconError::TypeError("amplifier initializer", "other initializer").at(pair.as_span()))
     } else {
         let mut amp = AmplifierBlock::default();
-        for item in pair.into_inner() {
-            let mut inner = item.into_inner();
-            let key = inner.next().unwrap().as_str();
-
-            let value = inner.next().unwrap();
+        parse_parameters(pair, BlockType::Amplifier, |key_pair, value| {
+            let key = key_pair.as_str();
             let rhs = parse_param_rhs(value, driver)?;
 
-            if key.starts_with("source") || key.starts_with("src") {
-                let n: usize = if key.starts_with("source") {
-                    key.chars()
-                        .skip("source".len())
-                        .collect::<String>()
-                        .parse()
-                        .map_err(|_| HarmoniconError::UnknownProperty(key.to_owned(), "amplifier"))?
-                } else if key.starts_with("src") {
-                    key.chars()
-                        .skip("src".len())
-                        .collect::<String>()
-                        .parse()
-                        .map_err(|_| HarmoniconError::UnknownProperty(key.to_owned(), "amplifier"))?
-                } else {
-                    return Err(HarmoniconError::UnknownProperty(key.to_owned(), "amplifier"));
-                };
-
-                amp.update_source(n, true, rhs);
-            } else if key.starts_with("amplify") || key.starts_with("amp") {
-                let n: usize = if key.starts_with("amplify") {
-                    key.chars()
-                        .skip("source".len())
-                        .collect::<String>()
-                        .parse()
-                        .map_err(|_| HarmoniconError::UnknownProperty(key.to_owned(), "amplifier"))?
-                } else if key.starts_with("amp") {
-                    key.chars()
-                        .skip("src".len())
-                        .collect::<String>()
-                        .parse()
-                        .map_err(|_| HarmoniconError::UnknownProperty(key.to_owned(), "amplifier"))?
-                } else {
-                    return Err(HarmoniconError::UnknownProperty(key.to_owned(), "amplifier"));
-                };
-
-                amp.update_source(n, false, rhs);
-            } else {
-                return Err(HarmoniconError::UnknownProperty(key.to_owned(), "amplifier"));
+            let (name, n) = split_numbered(key)
+                .ok_or_else(|| unknown_property(&key_pair, BlockType::Amplifier))?;
+            match name {
+                "source" | "src" => amp.update_source(n, true, rhs),
+                "amplify" | "amp" => amp.update_source(n, false, rhs),
+                _ => return Err(unknown_property(&key_pair, BlockType::Amplifier)),
             }
-        }
+            Ok(())
+        })?;
         Ok(amp)
     }
 }
 
 fn parse_stereo_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<StereoBlock> {
     if pair.as_rule() != Rule::block_initializer {
-        Err(HarmoniconError::TypeError("stereo initializer", "other initializer"))
+        Err(HarmoniconError::TypeError("stereo initializer", "other initializer").at(pair.as_span()))
     } else {
         let mut stereo = StereoBlock::default();
-        for item in pair.into_inner() {
-            let mut inner = item.into_inner();
-            let key = inner.next().unwrap().as_str();
-
-            let value = inner.next().unwrap();
-            let rhs = parse_param_rhs(value, driver)?;
+        parse_parameters(pair, BlockType::Stereo, |key_pair, value| {
+            let key = key_pair.as_str();
+
+            if key == "law" {
+                let law = match parse_keyword(value.clone()) {
+                    Some("linear" | "lin") => PanLaw::Linear,
+                    Some("equal-power" | "power") => PanLaw::EqualPower,
+                    _ => return Err(HarmoniconError::TypeError("pan law", "other").at(value.as_span())),
+                };
+                stereo.update_law(law);
+            } else {
+                let rhs = parse_param_rhs(value, driver)?;
 
-            match key {
-                "left" | "l" => stereo.update_left(rhs),
-                "right" | "r" => stereo.update_right(rhs),
-                "shift" | "s" => stereo.update_shift(rhs),
-                _ => return Err(HarmoniconError::UnknownProperty(key.to_owned(), "stereo")),
+                match key {
+                    "left" | "l" => stereo.update_left(rhs),
+                    "right" | "r" => stereo.update_right(rhs),
+                    "source" | "src" => stereo.update_source(rhs),
+                    "pan" | "shift" | "s" => stereo.update_pan(rhs),
+                    "width" | "w" => stereo.update_width(rhs),
+                    _ => return Err(unknown_property(&key_pair, BlockType::Stereo)),
+                }
             }
-        }
+            Ok(())
+        })?;
         Ok(stereo)
     }
 }
 
 fn parse_sequencer_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<SequencerBlock> {
     if pair.as_rule() != Rule::block_initializer {
-        Err(HarmoniconError::TypeError("stereo initializer", "other initializer"))
+        Err(HarmoniconError::TypeError("stereo initializer", "other initializer").at(pair.as_span()))
     } else {
         let mut sequencer = SequencerBlock::default();
-        for item in pair.into_inner() {
-            let mut inner = item.into_inner();
-            let key = inner.next().unwrap().as_str();
+        parse_parameters(pair, BlockType::Sequencer, |key_pair, value| {
+            let key = key_pair.as_str();
 
-            let value = inner.next().unwrap();
             if key == "seq" || key == "sequence" {
                 let seq = parse_sequence(value)?;
                 sequencer.update_sequence(seq);
+            } else if key == "velocity" || key == "vel" {
+                let velocities = parse_values(value)?;
+                sequencer.update_velocities(velocities);
             } else {
                 let rhs = parse_param_rhs(value, driver)?;
 
                 match key {
                     "bpm" => sequencer.update_bpm(rhs),
                     "spacing" => sequencer.update_spacing(rhs),
-                    _ => return Err(HarmoniconError::UnknownProperty(key.to_owned(), "sequencer")),
+                    _ => return Err(unknown_property(&key_pair, BlockType::Sequencer)),
                 }
             }
-        }
+            Ok(())
+        })?;
         Ok(sequencer)
     }
 }
 
+fn parse_env_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<EnvelopeBlock> {
+    if pair.as_rule() != Rule::block_initializer {
+        Err(HarmoniconError::TypeError("envelope initializer", "other initializer").at(pair.as_span()))
+    } else {
+        let mut env = EnvelopeBlock::default();
+        parse_parameters(pair, BlockType::Envelope, |key_pair, value| {
+            let key = key_pair.as_str();
+
+            let rhs = parse_param_rhs(value, driver)?;
+
+            match key {
+                "attack" | "a" => env.update_attack(rhs),
+                "decay" | "d" => env.update_decay(rhs),
+                "sustain" | "s" => env.update_sustain(rhs),
+                "release" | "r" => env.update_release(rhs),
+                "gate" | "g" => env.update_gate(rhs),
+                _ => return Err(unknown_property(&key_pair, BlockType::Envelope)),
+            }
+            Ok(())
+        })?;
+        Ok(env)
+    }
+}
+
+fn parse_filter_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<FilterBlock> {
+    if pair.as_rule() != Rule::block_initializer {
+        Err(HarmoniconError::TypeError("filter initializer", "other initializer").at(pair.as_span()))
+    } else {
+        let mut filter = FilterBlock::default();
+        parse_parameters(pair, BlockType::Filter, |key_pair, value| {
+            let key = key_pair.as_str();
+
+            if key == "mode" {
+                let mode = match parse_keyword(value.clone()) {
+                    Some("lowpass" | "lp") => FilterMode::Lowpass,
+                    Some("highpass" | "hp") => FilterMode::Highpass,
+                    Some("bandpass" | "bp") => FilterMode::Bandpass,
+                    Some("notch") => FilterMode::Notch,
+                    Some("peak") => FilterMode::Peak,
+                    _ => return Err(HarmoniconError::TypeError("filter mode", "other").at(value.as_span())),
+                };
+                filter.update_mode(mode);
+            } else {
+                let rhs = parse_param_rhs(value, driver)?;
+                match key {
+                    "source" | "src" => filter.update_source(rhs),
+                    "cutoff" | "freq" => filter.update_cutoff(rhs),
+                    "resonance" | "res" | "q" => filter.update_resonance(rhs),
+                    "gain" => filter.update_gain(rhs),
+                    _ => return Err(unknown_property(&key_pair, BlockType::Filter)),
+                }
+            }
+            Ok(())
+        })?;
+        Ok(filter)
+    }
+}
+
+fn parse_delay_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<DelayBlock> {
+    if pair.as_rule() != Rule::block_initializer {
+        Err(HarmoniconError::TypeError("delay initializer", "other initializer").at(pair.as_span()))
+    } else {
+        let mut delay = DelayBlock::default();
+        parse_parameters(pair, BlockType::Delay, |key_pair, value| {
+            let key = key_pair.as_str();
+
+            let rhs = parse_param_rhs(value, driver)?;
+
+            match key {
+                "source" | "src" => delay.update_source(rhs),
+                "time" | "t" => delay.update_time(rhs, TimeUnit::Seconds),
+                "beats" => delay.update_time(rhs, TimeUnit::Beats),
+                "bpm" => delay.update_bpm(rhs),
+                "feedback" | "fb" => delay.update_feedback(rhs),
+                "mix" => delay.update_mix(rhs),
+                _ => return Err(unknown_property(&key_pair, BlockType::Delay)),
+            }
+            Ok(())
+        })?;
+        Ok(delay)
+    }
+}
+
+fn parse_reverb_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<ReverbBlock> {
+    if pair.as_rule() != Rule::block_initializer {
+        Err(HarmoniconError::TypeError("reverb initializer", "other initializer").at(pair.as_span()))
+    } else {
+        let mut reverb = ReverbBlock::default();
+        parse_parameters(pair, BlockType::Reverb, |key_pair, value| {
+            let key = key_pair.as_str();
+
+            let rhs = parse_param_rhs(value, driver)?;
+
+            match key {
+                "source" | "src" => reverb.update_source(rhs),
+                "size" | "room" => reverb.update_size(rhs),
+                "damping" | "damp" => reverb.update_damping(rhs),
+                "width" => reverb.update_width(rhs),
+                "mix" => reverb.update_mix(rhs),
+                _ => return Err(unknown_property(&key_pair, BlockType::Reverb)),
+            }
+            Ok(())
+        })?;
+        Ok(reverb)
+    }
+}
+
+fn parse_noise_init(pair: Pair<'_, Rule>) -> crate::Result<NoiseBlock> {
+    if pair.as_rule() != Rule::block_initializer {
+        Err(HarmoniconError::TypeError("noise initializer", "other initializer").at(pair.as_span()))
+    } else {
+        let mut noise = NoiseBlock::default();
+        parse_parameters(pair, BlockType::Noise, |key_pair, value| {
+            let key = key_pair.as_str();
+
+            match key {
+                "color" | "colour" => {
+                    let color = match parse_keyword(value.clone()) {
+                        Some("white") => NoiseColor::White,
+                        Some("pink") => NoiseColor::Pink,
+                        Some("brown" | "red") => NoiseColor::Brown,
+                        _ => return Err(HarmoniconError::TypeError("noise color", "other").at(value.as_span())),
+                    };
+                    noise.update_color(color);
+                },
+                "seed" => noise.update_seed(parse_number(value)? as u64),
+                _ => return Err(unknown_property(&key_pair, BlockType::Noise)),
+            }
+            Ok(())
+        })?;
+        Ok(noise)
+    }
+}
+
+fn parse_sample_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<SampleBlock> {
+    if pair.as_rule() != Rule::block_initializer {
+        Err(HarmoniconError::TypeError("sample initializer", "other initializer").at(pair.as_span()))
+    } else {
+        let mut sample = SampleBlock::default();
+        parse_parameters(pair, BlockType::Sample, |key_pair, value| {
+            let key = key_pair.as_str();
+
+            if key == "file" {
+                let file = parse_string(value.clone())?;
+                sample.load(&driver.base_dir().join(file))
+                    .map_err(|e| e.at(value.as_span()))?;
+            } else if key == "loop" && parse_keyword(value.clone()).is_some_and(|word| driver.get_block(word).is_none()) {
+                // a plain flag like the mixer's mute and solo, but looping can also follow a signal
+                let looping = if parse_bool(value)? { 1.0 } else { 0.0 };
+                sample.update_loop(SignalSource::new_anonymous(ConstantBlock::new(looping)));
+            } else {
+                let rhs = parse_param_rhs(value, driver)?;
+
+                match key {
+                    "trigger" | "trig" => sample.update_trigger(rhs),
+                    "rate" => sample.update_rate(rhs),
+                    "start" => sample.update_start(rhs),
+                    "end" => sample.update_end(rhs),
+                    "loop" => sample.update_loop(rhs),
+                    _ => return Err(unknown_property(&key_pair, BlockType::Sample)),
+                }
+            }
+            Ok(())
+        })?;
+        Ok(sample)
+    }
+}
+
+// split keys like "gain12" into their name and channel number
+fn split_numbered(key: &str) -> Option<(&str, usize)> {
+    let name = key.trim_end_matches(|c: char| c.is_ascii_digit());
+    let n = key[name.len()..].parse().ok()?;
+    Some((name, n))
+}
+
+fn parse_mixer_init(pair: Pair<'_, Rule>, driver: &HarmoniconDriver) -> crate::Result<MixerBlock> {
+    if pair.as_rule() != Rule::block_initializer {
+        Err(HarmoniconError::TypeError("mixer initializer", "other initializer").at(pair.as_span()))
+    } else {
+        let mut mixer = MixerBlock::default();
+        parse_parameters(pair, BlockType::Mixer, |key_pair, value| {
+            let key = key_pair.as_str();
+
+            if key == "gain" || key == "master" {
+                mixer.update_gain(parse_param_rhs(value, driver)?);
+                return Ok(());
+            }
+
+            let (name, n) = split_numbered(key)
+                .ok_or_else(|| unknown_property(&key_pair, BlockType::Mixer))?;
+            match name {
+                "input" | "in" => mixer.update_input(n, parse_param_rhs(value, driver)?),
+                "gain" => mixer.update_channel_gain(n, parse_param_rhs(value, driver)?),
+                "pan" => mixer.update_pan(n, parse_param_rhs(value, driver)?),
+                "mute" => mixer.update_mute(n, parse_bool(value)?),
+                "solo" => mixer.update_solo(n, parse_bool(value)?),
+                _ => return Err(unknown_property(&key_pair, BlockType::Mixer)),
+            }
+            Ok(())
+        })?;
+        Ok(mixer)
+    }
+}
+
 fn parse_const_init(pair: Pair<'_, Rule>) -> crate::Result<ConstantBlock> {
     if pair.as_rule() != Rule::const_initializer {
-        Err(HarmoniconError::TypeError("constant initializer", "other initializer"))
+        Err(HarmoniconError::TypeError("constant initializer", "other initializer").at(pair.as_span()))
     } else {
         let val = pair.as_str().parse().unwrap();
         Ok(ConstantBlock::new(val))
@@ -207,13 +579,15 @@ fn parse_const_init(pair: Pair<'_, Rule>) -> crate::Result<ConstantBlock> {
 }
 
 
-pub fn parse_stage2(pair: Pair<'_, Rule>) -> crate::Result<HarmoniconDriver> {
+pub fn parse_stage2(pair: Pair<'_, Rule>, base_dir: &Path, sample_rate: u32) -> crate::Result<HarmoniconDriver> {
     if pair.as_rule() != Rule::file {
         panic!("Unexpected rule {:?}", pair.as_rule());
     }
 
-    let mut driver = HarmoniconDriver::new();
+    let mut driver = HarmoniconDriver::new(sample_rate);
+    driver.set_base_dir(base_dir);
     let mut last_block = None;
+    let mut errors = Vec::new();
     let instructions: Vec<_> = pair.into_inner().collect();
 
     for assgn_pair in instructions.iter().filter(|p| p.as_rule() == Rule::assignment) {
@@ -228,35 +602,95 @@ pub fn parse_stage2(pair: Pair<'_, Rule>) -> crate::Result<HarmoniconDriver> {
             let rhs = rhs.into_inner().next().unwrap();
             use BlockType::*;
             let block = match str::parse(type_str.as_str()).unwrap() {
-                Constant => driver.register_block(name.to_owned(), parse_const_init(rhs)?),
-                Oscillator => driver.register_block(name.to_owned(), parse_osc_init(rhs, &driver)?),
-                Amplifier => driver.register_block(name.to_owned(), parse_amp_init(rhs, &driver)?),
-                Stereo => driver.register_block(name.to_owned(), parse_stereo_init(rhs, &driver)?),
-                Sequencer => driver.register_block(name.to_owned(), parse_sequencer_init(rhs, &driver)?),
+                Constant => parse_const_init(rhs).map(|b| driver.register_block(name.to_owned(), b)),
+                Oscillator => parse_osc_init(rhs, &driver).map(|b| driver.register_block(name.to_owned(), b)),
+                Amplifier => parse_amp_init(rhs, &driver).map(|b| driver.register_block(name.to_owned(), b)),
+                Stereo => parse_stereo_init(rhs, &driver).map(|b| driver.register_block(name.to_owned(), b)),
+                Sequencer => parse_sequencer_init(rhs, &driver).map(|b| driver.register_block(name.to_owned(), b)),
+                Envelope => parse_env_init(rhs, &driver).map(|b| driver.register_block(name.to_owned(), b)),
+                Filter => parse_filter_init(rhs, &driver).map(|b| driver.register_block(name.to_owned(), b)),
+                Delay => parse_delay_init(rhs, &driver).map(|b| driver.register_block(name.to_owned(), b)),
+                Reverb => parse_reverb_init(rhs, &driver).map(|b| driver.register_block(name.to_owned(), b)),
+                Noise => parse_noise_init(rhs).map(|b| driver.register_block(name.to_owned(), b)),
+                Sample => parse_sample_init(rhs, &driver).map(|b| driver.register_block(name.to_owned(), b)),
+                Mixer => parse_mixer_init(rhs, &driver).map(|b| driver.register_block(name.to_owned(), b)),
+                Expression | Transport => panic!("Parser should have ensured this is not reachable (type: {:?})", type_str.as_str()),
             };
+            let block = block.unwrap_or_else(|e| {
+                errors.push(e);
+                // stand-in, so that later references to the block do not raise follow-up errors
+                let id = driver.register_block(name.to_owned(), ConstantBlock::new(0.0));
+                driver.mark_failed(id);
+                id
+            });
             last_block = Some(block);
         } else if rhs.as_rule() == Rule::name {
-            let block = driver.alias_block(rhs.as_str(), name.to_owned())
-                .ok_or(HarmoniconError::UnknownBlock(rhs.as_str().to_owned()))?;
-            last_block = Some(block.clone());
+            let block = driver.alias_block(rhs.as_str(), name.to_owned()).unwrap_or_else(|| {
+                errors.push(unknown_block(&rhs, &driver));
+                let id = driver.register_block(name.to_owned(), ConstantBlock::new(0.0));
+                driver.mark_failed(id);
+                id
+            });
+            last_block = Some(block);
         } else {
             panic!("Parser should have ensured this is not reachable (rule: {:?})", rhs.as_rule());
         }
     }
 
+    let tempo_opt = instructions.iter()
+        .rfind(|p| p.as_rule() == Rule::tempo);
+    if let Some(tempo) = tempo_opt {
+        let mut inner = tempo.clone().into_inner();
+        let bpm_pair = inner.next().unwrap();
+        let mut bpm = str::parse(bpm_pair.as_str()).unwrap();
+        // the transport would stand still and quantised reloads would never land
+        if bpm <= 0.0 {
+            errors.push(HarmoniconError::TypeError("positive tempo", "number").at(bpm_pair.as_span()));
+            bpm = 120.0;
+        }
+        let beats_per_bar = match inner.next() {
+            Some(beats) => str::parse(beats.as_str()).unwrap_or_else(|_| {
+                errors.push(HarmoniconError::TypeError("integer", "number").at(beats.as_span()));
+                4
+            }),
+            None => 4,
+        };
+        driver.set_transport(Transport::new(bpm, beats_per_bar, sample_rate));
+    }
+
+    let quantize_opt = instructions.iter()
+        .rfind(|p| p.as_rule() == Rule::quantize);
+    if let Some(quantize) = quantize_opt {
+        let grid = quantize.clone().into_inner().next().unwrap().into_inner().next().unwrap();
+        driver.set_quantize(match grid.as_rule() {
+            Rule::quantize_off => Quantize::Off,
+            Rule::quantize_beat => Quantize::Beat,
+            Rule::quantize_bar => Quantize::Bar,
+            _ => panic!("Parser should have ensured this is not reachable (rule: {:?})", grid.as_rule()),
+        });
+    }
+
     let output_opt = instructions.iter()
         .filter(|p| p.as_rule() == Rule::output)
-        .map(|r| r.clone().into_inner().next().unwrap().as_str())
-        .last();
+        .map(|r| r.clone().into_inner().next().unwrap())
+        .next_back();
     if let Some(name) = output_opt {
-        match driver.get_block(name) {
-            Some(out) => driver.set_output(out.clone()),
-            None => return Err(HarmoniconError::UnknownOutput(name.to_string())),
+        match driver.get_block(name.as_str()) {
+            Some(out) => driver.set_output(out),
+            None => {
+                let suggestion = Suggestion::closest(name.as_str(), driver.block_names());
+                errors.push(HarmoniconError::UnknownOutput(name.as_str().to_string(), suggestion).at(name.as_span()));
+            },
         }
     } else if let Some(last) = last_block {
         driver.set_output(last);
     }
 
+    // a patch with errors is never handed out, even though the rest of it parsed
+    HarmoniconError::collect(errors)?;
+
+    driver.build_schedule();
+    driver.prepare();
     Ok(driver)
 }
 
@@ -265,3 +699,44 @@ pub fn parse_stage1(input: &str) -> crate::Result<Pair<'_, Rule>> {
         .next()
         .unwrap())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every key a block advertises must reach its parser, even if the value does not fit
+    #[test]
+    fn advertised_parameters_are_accepted() {
+        use BlockType::*;
+        for block_type in [Oscillator, Amplifier, Stereo, Sequencer, Envelope, Filter, Delay, Reverb, Noise, Sample, Mixer] {
+            for parameter in block_type.parameters() {
+                let key = parameter.replace('#', "1");
+                let source = format!("{} x = {{ {key}: 1.0 }}", block_type.name());
+                let Err(error) = parse_stage2(parse_stage1(&source).unwrap(), Path::new("."), 44100) else { continue };
+                for error in error.errors() {
+                    let error = match error {
+                        HarmoniconError::Spanned(error, _) => &**error,
+                        error => error,
+                    };
+                    assert!(!matches!(error, HarmoniconError::UnknownProperty(..)), "{source}: {error}");
+                }
+            }
+        }
+    }
+
+    // the reload thread must survive values of the wrong shape for any key
+    #[test]
+    fn mismatched_values_are_errors() {
+        use BlockType::*;
+        for block_type in [Oscillator, Amplifier, Stereo, Sequencer, Envelope, Filter, Delay, Reverb, Noise, Sample, Mixer] {
+            for parameter in block_type.parameters() {
+                let key = parameter.replace('#', "1");
+                for value in ["[]", "[A4]", "\"missing.wav\"", "lp * 2.0", "x"] {
+                    let source = format!("{} x = {{ {key}: {value} }}", block_type.name());
+                    let _ = parse_stage2(parse_stage1(&source).unwrap(), Path::new("."), 44100);
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::fs;
+use std::{fs, mem};
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 use std::sync::mpsc::Receiver;
@@ -7,21 +7,42 @@ use std::thread;
 
 use notify::{RecursiveMode, Watcher};
 
-use crate::driver::HarmoniconDriver;
+use crate::driver::{HarmoniconDriver, Patch};
 use crate::error::HarmoniconError;
+use crate::graph::{BlockId, Graph};
+use crate::HashMap;
 
-pub fn start_reload_thread(file: PathBuf) -> Receiver<HarmoniconDriver> {
+pub fn start_reload_thread(driver: &HarmoniconDriver, file: PathBuf) -> Receiver<Patch> {
     let (tx, rx) = mpsc::channel();
-    thread::spawn(move || reload_thread(file, tx));
+    let dependencies = driver.dependencies();
+    let block_ids = driver.block_ids();
+    let sample_rate = driver.sample_rate();
+    thread::spawn(move || reload_thread(file, dependencies, block_ids, sample_rate, tx));
     rx
 }
 
-fn reload_thread(file: PathBuf, tx: Sender<HarmoniconDriver>) {
+/// Graphs retired by the audio thread are freed here, since dropping them
+/// (delay lines, reverb buffers, ...) is not real-time safe
+pub fn start_retire_thread() -> Sender<Graph> {
+    let (tx, rx) = mpsc::channel::<Graph>();
+    thread::spawn(move || rx.into_iter().for_each(drop));
+    tx
+}
+
+fn reload_thread(
+    file: PathBuf,
+    mut dependencies: Vec<PathBuf>,
+    mut block_ids: HashMap<String, BlockId>,
+    sample_rate: u32,
+    tx: Sender<Patch>,
+) {
     // TODO: replace unwraps
     let (event_tx, event_rx) = mpsc::channel();
 
     let mut watcher = notify::recommended_watcher(event_tx).unwrap();
     watcher.watch(&file, RecursiveMode::NonRecursive).unwrap();
+    watch_dependencies(&mut watcher, &dependencies);
+    let mut generation = 0;
 
     for res in event_rx {
         if let Err(e) = res {
@@ -34,10 +55,30 @@ fn reload_thread(file: PathBuf, tx: Sender<HarmoniconDriver>) {
         }
 
         println!("reloading...");
-        match HarmoniconDriver::parse_from_file(&file) {
-            Ok(driver) => tx.send(driver).unwrap(),
-            Err(e) => e.warn(),
+        match HarmoniconDriver::parse_from_file(&file, sample_rate) {
+            Ok(driver) => {
+                for dep in &dependencies {
+                    let _ = watcher.unwatch(dep);
+                }
+                dependencies = driver.dependencies();
+                generation += 1;
+                let previous = mem::replace(&mut block_ids, driver.block_ids());
+                tx.send(driver.into_patch(&previous, generation)).unwrap()
+            },
+            Err(e) => {
+                e.warn();
+                println!("reload failed with {}, keeping the current patch", e.summary());
+            },
         }
         watcher.watch(&file, RecursiveMode::NonRecursive).unwrap();
+        watch_dependencies(&mut watcher, &dependencies);
+    }
+}
+
+fn watch_dependencies(watcher: &mut impl Watcher, dependencies: &[PathBuf]) {
+    for dep in dependencies {
+        if let Err(e) = watcher.watch(dep, RecursiveMode::NonRecursive) {
+            HarmoniconError::from(e).warn();
+        }
     }
 }
@@ -0,0 +1,52 @@
+use std::path::Path;
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::driver::HarmoniconDriver;
+
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+pub enum BitDepth {
+    #[clap(name = "16")]
+    Int16,
+    #[clap(name = "24")]
+    Int24,
+    #[clap(name = "32")]
+    Float32,
+}
+
+impl BitDepth {
+    fn spec(self, sample_rate: u32) -> WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            BitDepth::Int16 => (16, SampleFormat::Int),
+            BitDepth::Int24 => (24, SampleFormat::Int),
+            BitDepth::Float32 => (32, SampleFormat::Float),
+        };
+
+        WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample,
+            sample_format,
+        }
+    }
+}
+
+pub fn render_to_file(driver: HarmoniconDriver, file: &Path, duration: f32, start: f32, depth: BitDepth) -> crate::Result<()> {
+    let spec = depth.spec(driver.sample_rate());
+    let mut writer = WavWriter::create(file, spec)?;
+
+    let skip = (start * spec.sample_rate as f32) as usize * spec.channels as usize;
+    let take = (duration * spec.sample_rate as f32) as usize * spec.channels as usize;
+
+    for sample in driver.skip(skip).take(take) {
+        // integer formats cannot hold anything outside full scale, float keeps the headroom
+        match depth {
+            BitDepth::Int16 => writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?,
+            BitDepth::Int24 => writer.write_sample((sample.clamp(-1.0, 1.0) * 8_388_607.0) as i32)?,
+            BitDepth::Float32 => writer.write_sample(sample)?,
+        }
+    }
+
+    writer.finalize()?;
+    Ok(())
+}
@@ -0,0 +1,105 @@
+#[derive(Copy, Clone, Debug)]
+pub struct Transport {
+    bpm: f32,
+    beats_per_bar: u32,
+    sample_rate: u32,
+    /// Beat at frame zero, where the transport was started or took over from a previous one
+    origin: f64,
+    /// Frames since the origin. The position is derived from it rather than
+    /// accumulated, so that it does not drift off the grid.
+    frame: u64,
+    last_beat: f64,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum Quantize {
+    #[default]
+    Off,
+    Beat,
+    Bar,
+}
+
+
+impl Transport {
+    pub fn new(bpm: f32, beats_per_bar: u32, sample_rate: u32) -> Self {
+        Transport {
+            bpm,
+            beats_per_bar: beats_per_bar.max(1),
+            sample_rate,
+            origin: 0.0,
+            frame: 0,
+            last_beat: -1.0,
+        }
+    }
+
+    pub fn advance(&mut self) {
+        self.last_beat = self.beat();
+        self.frame += 1;
+    }
+
+    /// Continue from the position of another transport, possibly at a different tempo
+    pub fn sync_from(&mut self, other: &Transport) {
+        self.origin = other.beat();
+        self.frame = 0;
+        self.last_beat = other.last_beat;
+    }
+
+    /// Number of frames, at most `max`, that can be rendered before the grid is reached
+    pub fn frames_until(&self, grid: Quantize, max: usize) -> usize {
+        let mut transport = *self;
+        for n in 1..max {
+            transport.advance();
+            if transport.on_grid(grid) {
+                return n;
+            }
+        }
+        max
+    }
+
+    /// Whether the current sample is the first one of a new beat or bar
+    pub fn on_grid(&self, grid: Quantize) -> bool {
+        let beats_per_bar = self.beats_per_bar as f64;
+        let beat = self.beat();
+        match grid {
+            Quantize::Off => true,
+            Quantize::Beat => beat.floor() != self.last_beat.floor(),
+            Quantize::Bar => (beat / beats_per_bar).floor() != (self.last_beat / beats_per_bar).floor(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn bpm(&self) -> f32 {
+        self.bpm
+    }
+
+    pub fn beat(&self) -> f64 {
+        self.origin + self.frame as f64 * self.bpm as f64 / (self.sample_rate as f64 * 60.0)
+    }
+
+    pub fn bar(&self) -> f64 {
+        self.beat() / self.beats_per_bar as f64
+    }
+
+    pub fn phase(&self) -> f32 {
+        self.beat().fract() as f32
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_does_not_drift() {
+        let mut transport = Transport::new(60.0, 4, 1000);
+        assert!(transport.on_grid(Quantize::Bar));
+        for frame in 1..=40_000 {
+            transport.advance();
+            assert_eq!(transport.on_grid(Quantize::Bar), frame % 4000 == 0, "frame {frame}");
+        }
+    }
+}